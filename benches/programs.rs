@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use my_lang::{Program, Scope};
+
+// programs/test.jcw sums the collatz depths of 1 to 99999, which is mostly spent calling a user function
+// and reading and writing variables. each run prints its total once
+fn test_program(c: &mut Criterion) {
+    let text = fs::read_to_string("programs/test.jcw").unwrap();
+    let mut user_fns = HashMap::new();
+    let program = Program::parse(&text, 0, &mut user_fns).unwrap();
+    let globals = program.globals();
+    for func in user_fns.values() {
+        func.optimise(&user_fns, &globals).unwrap();
+    }
+    let program = program.optimise(&user_fns, &mut Scope::new(globals)).unwrap();
+
+    let mut group = c.benchmark_group("programs");
+    group.sample_size(10).measurement_time(Duration::from_secs(60));
+    group.bench_function("test.jcw", |b| b.iter(|| program.start().unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, test_program);
+criterion_main!(benches);
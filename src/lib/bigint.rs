@@ -0,0 +1,565 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+// an int of any size. the digits are base 2^32, least significant first, with no zero digits at the end, so
+// zero has no digits and is never negative
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+// the largest power of ten that fits in a digit, for converting to and from decimal
+const DECIMAL_BASE: u32 = 1_000_000_000;
+const DECIMAL_DIGITS: usize = 9;
+
+impl BigInt {
+    fn new(negative: bool, mut digits: Vec<u32>) -> BigInt {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        BigInt { negative: negative && !digits.is_empty(), digits }
+    }
+
+    // a decimal int, with a minus sign in front if it's negative
+    pub fn parse(text: &str) -> Option<BigInt> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut digits = Vec::new();
+        // the first chunk is whatever's left over so the rest have exactly `DECIMAL_DIGITS` digits
+        let first = match text.len() % DECIMAL_DIGITS {
+            0 => DECIMAL_DIGITS,
+            len => len,
+        };
+        let mut start = 0;
+        let mut end = first;
+        while start < text.len() {
+            let chunk = text[start..end].parse().ok()?;
+            mul_small_add(&mut digits, DECIMAL_BASE, chunk);
+            start = end;
+            end += DECIMAL_DIGITS;
+        }
+        Some(BigInt::new(negative, digits))
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    // -1, 0 or 1
+    pub fn signum(&self) -> i64 {
+        match (self.negative, self.digits.is_empty()) {
+            (true, _) => -1,
+            (false, true) => 0,
+            (false, false) => 1,
+        }
+    }
+
+    // how many bits the magnitude takes up
+    pub fn bits(&self) -> u64 {
+        match self.digits.last() {
+            Some(top) => self.digits.len() as u64 * 32 - u64::from(top.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt { negative: false, digits: self.digits.clone() }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = i128::from(self.low_bits());
+        i64::try_from(if self.negative { -magnitude } else { magnitude }).ok()
+    }
+
+    // the lowest 64 bits of the two's complement of the int
+    pub fn wrap_i64(&self) -> i64 {
+        let low = self.low_bits() as i64;
+        if self.negative { low.wrapping_neg() } else { low }
+    }
+
+    // the nearest float, which is infinite if the int is too big for one
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.digits.iter().rev().fold(0.0, |x, &d| x * 4_294_967_296.0 + f64::from(d));
+        if self.negative { -magnitude } else { magnitude }
+    }
+
+    pub fn pow(&self, mut exp: u32) -> BigInt {
+        let mut base = self.clone();
+        let mut result = BigInt::from(1i64);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = &result * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    // the quotient rounded towards zero, and a remainder with the same sign as `self`, like the ones ints
+    // give. the divisor mustn't be zero
+    pub fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        let (quotient, remainder) = div_rem_digits(&self.digits, &other.digits);
+        (BigInt::new(self.negative != other.negative, quotient), BigInt::new(self.negative, remainder))
+    }
+
+    // the greatest common divisor of the two, which is never negative and is only zero if they both are
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.digits.is_empty() {
+            let remainder = &a % &b;
+            a = b;
+            b = remainder;
+        }
+        a
+    }
+
+    // the square root rounded down, of an int that isn't negative. newton's method comes down to it from
+    // any first guess that's at least as big, without ever going below it
+    pub fn isqrt(&self) -> BigInt {
+        if self.digits.is_empty() {
+            return self.clone();
+        }
+        let two = BigInt::from(2i64);
+        let mut root = two.pow(self.bits().div_ceil(2) as u32);
+        loop {
+            let next = &(&root + &(self / &root)) / &two;
+            if next >= root {
+                return root;
+            }
+            root = next;
+        }
+    }
+
+    // `self` to the power of `exp`, modulo `modulus`, between 0 and `modulus` even when `self` is negative.
+    // `exp` can't be negative and `modulus` has to be positive
+    pub fn pow_mod(&self, exp: &BigInt, modulus: &BigInt) -> BigInt {
+        let mut base = self % modulus;
+        if base.negative {
+            base = &base + modulus;
+        }
+        let mut result = &BigInt::from(1i64) % modulus;
+        for bit in 0..exp.bits() {
+            if exp.digits[(bit / 32) as usize] >> (bit % 32) & 1 == 1 {
+                result = &(&result * &base) % modulus;
+            }
+            base = &(&base * &base) % modulus;
+        }
+        result
+    }
+
+    fn low_bits(&self) -> u64 {
+        let digit = |i| u64::from(self.digits.get(i).copied().unwrap_or(0));
+        digit(0) | digit(1) << 32
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        BigInt::from(i128::from(n))
+    }
+}
+
+impl From<i128> for BigInt {
+    fn from(n: i128) -> Self {
+        let mut magnitude = n.unsigned_abs();
+        let mut digits = Vec::new();
+        while magnitude > 0 {
+            digits.push(magnitude as u32);
+            magnitude >>= 32;
+        }
+        BigInt::new(n < 0, digits)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, false) => cmp_digits(&self.digits, &other.digits),
+            (true, true) => cmp_digits(&other.digits, &self.digits),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.digits.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_digits(&self.digits, &other.digits));
+        }
+        // the sign is the sign of whichever has the larger magnitude
+        match cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::new(other.negative, sub_digits(&other.digits, &self.digits)),
+            _ => BigInt::new(self.negative, sub_digits(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(self.negative != other.negative, mul_digits(&self.digits, &other.digits))
+    }
+}
+
+impl Div for &BigInt {
+    type Output = BigInt;
+
+    fn div(self, other: &BigInt) -> BigInt {
+        self.div_rem(other).0
+    }
+}
+
+impl Rem for &BigInt {
+    type Output = BigInt;
+
+    fn rem(self, other: &BigInt) -> BigInt {
+        self.div_rem(other).1
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.digits.is_empty() {
+            return write!(f, "0");
+        }
+        // the decimal digits in chunks, least significant first
+        let mut chunks = Vec::new();
+        let mut digits = self.digits.clone();
+        while !digits.is_empty() {
+            chunks.push(div_small(&mut digits, DECIMAL_BASE));
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for chunk in chunks {
+            write!(f, "{:0width$}", chunk, width = DECIMAL_DIGITS)?;
+        }
+        Ok(())
+    }
+}
+
+// the functions below work on the magnitudes of ints, as digits least significant first
+
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0;
+    for (i, &d) in long.iter().enumerate() {
+        let total = u64::from(d) + u64::from(short.get(i).copied().unwrap_or(0)) + carry;
+        sum.push(total as u32);
+        carry = total >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+// `a` mustn't be smaller than `b`
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, &d) in a.iter().enumerate() {
+        let total = i64::from(d) - i64::from(b.get(i).copied().unwrap_or(0)) - borrow;
+        difference.push(total as u32);
+        borrow = if total < 0 { 1 } else { 0 };
+    }
+    difference
+}
+
+fn mul_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut product = vec![0; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, &y) in b.iter().enumerate() {
+            let total = u64::from(x) * u64::from(y) + u64::from(product[i + j]) + carry;
+            product[i + j] = total as u32;
+            carry = total >> 32;
+        }
+        product[i + b.len()] = carry as u32;
+    }
+    product
+}
+
+// `digits * factor + add`, in place
+fn mul_small_add(digits: &mut Vec<u32>, factor: u32, add: u32) {
+    let mut carry = u64::from(add);
+    for d in digits.iter_mut() {
+        let total = u64::from(*d) * u64::from(factor) + carry;
+        *d = total as u32;
+        carry = total >> 32;
+    }
+    if carry > 0 {
+        digits.push(carry as u32);
+    }
+}
+
+// divides in place, giving back the remainder. the digits stay without zeros at the end
+fn div_small(digits: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0;
+    for d in digits.iter_mut().rev() {
+        let total = remainder << 32 | u64::from(*d);
+        *d = (total / u64::from(divisor)) as u32;
+        remainder = total % u64::from(divisor);
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    remainder as u32
+}
+
+// long division, as in Knuth's algorithm D. `b` mustn't be zero
+fn div_rem_digits(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_digits(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if let [divisor] = b {
+        let mut quotient = a.to_vec();
+        let remainder = div_small(&mut quotient, *divisor);
+        return (quotient, vec![remainder]);
+    }
+
+    // shifting both so the top bit of the divisor is set keeps the guesses at each digit of the quotient
+    // no more than 2 too big
+    let shift = b.last().unwrap().leading_zeros();
+    let mut b = shl_digits(b, shift);
+    b.pop();
+    let mut a = shl_digits(a, shift);
+    let n = b.len();
+    let (top, next) = (u64::from(b[n - 1]), u64::from(b[n - 2]));
+    let base = 1 << 32;
+
+    let mut quotient = vec![0; a.len() - n];
+    for j in (0..quotient.len()).rev() {
+        let numerator = u64::from(a[j + n]) << 32 | u64::from(a[j + n - 1]);
+        let mut guess = numerator / top;
+        let mut remainder = numerator % top;
+        while guess >= base || guess * next > (remainder << 32 | u64::from(a[j + n - 2])) {
+            guess -= 1;
+            remainder += top;
+            if remainder >= base {
+                break;
+            }
+        }
+
+        // subtract `guess * b` from the digits of `a` it lines up with
+        let mut borrow = 0i64;
+        for i in 0..n {
+            let product = guess * u64::from(b[i]);
+            let total = i64::from(a[i + j]) - borrow - (product & 0xffff_ffff) as i64;
+            a[i + j] = total as u32;
+            borrow = (product >> 32) as i64 - (total >> 32);
+        }
+        let total = i64::from(a[j + n]) - borrow;
+        a[j + n] = total as u32;
+
+        // the guess was one too big, so add `b` back on
+        if total < 0 {
+            guess -= 1;
+            let mut carry = 0;
+            for i in 0..n {
+                let total = u64::from(a[i + j]) + u64::from(b[i]) + carry;
+                a[i + j] = total as u32;
+                carry = total >> 32;
+            }
+            a[j + n] = a[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = guess as u32;
+    }
+
+    let remainder = (0..n)
+        .map(|i| match shift {
+            0 => a[i],
+            _ => a[i] >> shift | a[i + 1] << (32 - shift),
+        })
+        .collect();
+    (quotient, remainder)
+}
+
+// shifted left by fewer than 32 bits, with an extra digit for the bits shifted out of the top
+fn shl_digits(digits: &[u32], shift: u32) -> Vec<u32> {
+    let mut shifted = Vec::with_capacity(digits.len() + 1);
+    let mut carry = 0;
+    for &d in digits {
+        shifted.push(d << shift | carry);
+        carry = if shift == 0 { 0 } else { d >> (32 - shift) };
+    }
+    shifted.push(carry);
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(text: &str) -> BigInt {
+        BigInt::parse(text).unwrap()
+    }
+
+    #[test]
+    fn carries_across_digits() {
+        assert_eq!(&big("4294967295") + &big("1"), big("4294967296"));
+        assert_eq!(&big("18446744073709551615") + &big("1"), big("18446744073709551616"));
+        assert_eq!(&big("79228162514264337593543950335") + &big("1"), big("79228162514264337593543950336"));
+        assert_eq!(&big("-18446744073709551615") - &big("1"), big("-18446744073709551616"));
+        assert_eq!(&big("18446744073709551615") * &big("18446744073709551615"), big("340282366920938463426481119284349108225"));
+    }
+
+    #[test]
+    fn borrows_across_digits() {
+        assert_eq!(&big("18446744073709551616") - &big("1"), big("18446744073709551615"));
+        assert_eq!(&big("79228162514264337593543950336") - &big("18446744073709551616"), big("79228162495817593519834398720"));
+        assert_eq!(&big("-18446744073709551616") + &big("1"), big("-18446744073709551615"));
+        assert_eq!(&big("1") - &big("18446744073709551616"), big("-18446744073709551615"));
+        assert_eq!(&big("18446744073709551616") - &big("18446744073709551616"), big("0"));
+    }
+
+    // each of these has a digit of the quotient that's first guessed too big, either by enough to be caught
+    // before multiplying the divisor by it or only once the divisor times it has been taken away
+    #[test]
+    fn division_corrects_its_guesses() {
+        let cases = [
+            ("39614081257132168796771975171", "9903520314283042199192993793", "3", "9903520314283042199192993792"),
+            ("604462909807314587353091", "151115727451828646838273", "3", "151115727451828646838272"),
+            ("2596069201709362459734969208012800", "604462909807314587353089", "4294836224", "604462909807310292516864"),
+            ("2596148429267413814546714551386112", "140737488420863", "18446744065119748101", "140711718354949"),
+            ("170141183420855150474555134919112130560", "39614081257132168796771975169", "4294967294", "39614081257132168792477007874"),
+        ];
+        for (a, b, quotient, remainder) in cases {
+            assert_eq!(big(a).div_rem(&big(b)), (big(quotient), big(remainder)), "{} / {}", a, b);
+        }
+    }
+
+    // the quotient is rounded towards zero and the remainder has the sign of the dividend, like for ints
+    #[test]
+    fn division_signs() {
+        let (a, b) = (big("1180591620717411303425"), big("34359738368"));
+        assert_eq!(a.div_rem(&b), (big("34359738368"), big("1")));
+        assert_eq!((-&a).div_rem(&b), (big("-34359738368"), big("-1")));
+        assert_eq!(a.div_rem(&-&b), (big("-34359738368"), big("1")));
+        assert_eq!((-&a).div_rem(&-&b), (big("34359738368"), big("-1")));
+        assert_eq!(&big("-7") / &big("2"), big("-3"));
+        assert_eq!(&big("-7") % &big("2"), big("-1"));
+    }
+
+    // ints that fit in an i128 worked out both ways, with digits that carry and borrow all over the place
+    #[test]
+    fn arithmetic_matches_i128() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let bits = seed % 63;
+            (seed as i64 >> bits) as i128
+        };
+        for _ in 0..1000 {
+            let (a, b) = (next() * next(), next());
+            let (big_a, big_b) = (BigInt::from(a), BigInt::from(b));
+            assert_eq!((&big_a + &big_b).to_string(), (a + b).to_string());
+            assert_eq!((&big_a - &big_b).to_string(), (a - b).to_string());
+            if let Some(product) = a.checked_mul(b) {
+                assert_eq!((&big_a * &big_b).to_string(), product.to_string());
+            }
+            if b != 0 {
+                assert_eq!((&big_a / &big_b).to_string(), (a / b).to_string());
+                assert_eq!((&big_a % &big_b).to_string(), (a % b).to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for text in [
+            "0", "1", "-1", "999999999", "1000000000", "-1000000000", "4294967295", "4294967296", "-18446744073709551616",
+            "100000000000000000000000000001", "-340282366920938463426481119284349108225",
+        ] {
+            assert_eq!(big(text).to_string(), text);
+        }
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(big("000123").to_string(), "123");
+    }
+
+    #[test]
+    fn narrowing_to_i64() {
+        assert_eq!(BigInt::from(i64::MAX).to_i64(), Some(i64::MAX));
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!((&BigInt::from(i64::MAX) + &big("1")).to_i64(), None);
+        assert_eq!((&BigInt::from(i64::MIN) - &big("1")).to_i64(), None);
+        assert_eq!(big("18446744073709551616").to_i64(), None);
+        assert_eq!((&BigInt::from(i64::MAX) + &big("1")).wrap_i64(), i64::MIN);
+        assert_eq!((&BigInt::from(i64::MIN) - &big("1")).wrap_i64(), i64::MAX);
+        assert_eq!(big("18446744073709551617").wrap_i64(), 1);
+    }
+
+    #[test]
+    fn gcd_of_big_ints() {
+        assert_eq!(big("-99999999999999999999999999").gcd(&big("3")), big("3"));
+        assert_eq!(big("340282366920938463463374607431768211456").gcd(&big("-18446744073709551616")), big("18446744073709551616"));
+        assert_eq!(big("0").gcd(&big("-18446744073709551616")), big("18446744073709551616"));
+        assert_eq!(big("0").gcd(&big("0")), big("0"));
+    }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        let n = big("99999999999999999999999999");
+        assert_eq!((&n * &n).isqrt(), n);
+        assert_eq!((&(&n * &n) - &big("1")).isqrt(), &n - &big("1"));
+        assert_eq!(n.isqrt(), big("9999999999999"));
+        assert_eq!(big("0").isqrt(), big("0"));
+        assert_eq!(big("3").isqrt(), big("1"));
+    }
+
+    #[test]
+    fn pow_mod_is_never_negative() {
+        let n = big("99999999999999999999999999");
+        assert_eq!(n.pow_mod(&n, &big("1000007")), big("972188"));
+        assert_eq!((-&n).pow_mod(&big("3"), &big("10")), big("1"));
+        assert_eq!(n.pow_mod(&big("0"), &big("1")), big("0"));
+        assert_eq!(big("2").pow_mod(&big("100"), &big("1267650600228229401496703205377")), big("1267650600228229401496703205376"));
+    }
+}
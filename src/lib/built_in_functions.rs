@@ -1,265 +1,1056 @@
-use crate::lib::{DataStore, Expression};
+use crate::{BigInt, DataStore, ErrorKind, Expression, MyLangError, Overflow, Scope, Span, Value};
+use crate::overflow::{Arithmetic, ADD, DIV, MUL, REM, SUB};
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use crate::lib::user_function::UserFunction;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use crate::user_function::{Closure, UserFunction};
+use crate::value::{sorted_keys, type_error};
 
 #[derive(Debug)]
 pub enum BuiltIns<'a> {
-    Add(Expression<'a>, Expression<'a>),
-    Sub(Expression<'a>, Expression<'a>),
-    Mul(Expression<'a>, Expression<'a>),
+    // `+`, `-` and `*` work from left to right through their arguments, and `-` on its own negates
+    Add(Vec<Expression<'a>>),
+    Sub(Vec<Expression<'a>>),
+    Mul(Vec<Expression<'a>>),
     Div(Expression<'a>, Expression<'a>),
     Mod(Expression<'a>, Expression<'a>),
-    Eq(Expression<'a>, Expression<'a>),
-    Neq(Expression<'a>, Expression<'a>),
-    Lt(Expression<'a>, Expression<'a>),
-    Gt(Expression<'a>, Expression<'a>),
-    Le(Expression<'a>, Expression<'a>),
-    Ge(Expression<'a>, Expression<'a>),
+    Min(Vec<Expression<'a>>),
+    Max(Vec<Expression<'a>>),
+    // comparisons are chained, `< a b c` holds if `a` is less than `b` and `b` is less than `c`
+    Eq(Vec<Expression<'a>>),
+    Neq(Vec<Expression<'a>>),
+    Lt(Vec<Expression<'a>>),
+    Gt(Vec<Expression<'a>>),
+    Le(Vec<Expression<'a>>),
+    Ge(Vec<Expression<'a>>),
+    // `and` and `or` stop at the first argument that decides the result, without working out the rest
+    And(Vec<Expression<'a>>),
+    Or(Vec<Expression<'a>>),
+    // bitwise operators on ints
+    BitAnd(Vec<Expression<'a>>),
+    BitOr(Vec<Expression<'a>>),
+    BitXor(Vec<Expression<'a>>),
+    Shl(Expression<'a>, Expression<'a>),
+    Shr(Expression<'a>, Expression<'a>),
+    BitNot(Expression<'a>),
     Ternary(Expression<'a>, Expression<'a>, Expression<'a>),
     Not(Expression<'a>),
     Print(Vec<Expression<'a>>),
     Printa(Vec<Expression<'a>>),
+    Argc,
+    Arg(Expression<'a>),
+    Len(Expression<'a>),
+    Push(Expression<'a>, Expression<'a>),
+    Pop(Expression<'a>),
+    Slice(Expression<'a>, Expression<'a>, Expression<'a>),
+    Get(Expression<'a>, Expression<'a>, Expression<'a>),
+    Set(Expression<'a>, Expression<'a>, Expression<'a>),
+    Has(Expression<'a>, Expression<'a>),
+    Del(Expression<'a>, Expression<'a>),
+    Keys(Expression<'a>),
+    Call(Expression<'a>, Vec<Expression<'a>>),
+    Map(Expression<'a>, Expression<'a>),
+    Filter(Expression<'a>, Expression<'a>),
+    Reduce(Expression<'a>, Expression<'a>),
+    Fold(Expression<'a>, Expression<'a>, Expression<'a>),
+    Any(Expression<'a>, Expression<'a>),
+    All(Expression<'a>, Expression<'a>),
+    SortBy(Expression<'a>, Expression<'a>),
+    Zip(Expression<'a>, Expression<'a>),
+    Enumerate(Expression<'a>),
+    Range(Expression<'a>, Expression<'a>),
+    // maths. functions that only make sense for floats give a float even when given an int
+    Abs(Expression<'a>),
+    Sign(Expression<'a>),
+    Pow(Expression<'a>, Expression<'a>),
+    PowMod(Expression<'a>, Expression<'a>, Expression<'a>),
+    Isqrt(Expression<'a>),
+    Sqrt(Expression<'a>),
+    Gcd(Expression<'a>, Expression<'a>),
+    Lcm(Expression<'a>, Expression<'a>),
+    Clamp(Expression<'a>, Expression<'a>, Expression<'a>),
+    Log(Expression<'a>),
+    Sin(Expression<'a>),
+    Cos(Expression<'a>),
+    Tan(Expression<'a>),
+    Asin(Expression<'a>),
+    Acos(Expression<'a>),
+    Atan(Expression<'a>),
 }
 
 // defines standard math/logic operators and print
 impl<'a> BuiltIns<'a> {
-    pub fn get_function(line: &'a str, user_fns: &HashMap<&'a str, UserFunction<'a>>) -> Option<BuiltIns<'a>> {
-        match line.find(" ") {
-            Some(space) => {
-                let (func, args) = line.split_at(space);
-                let args = args.trim();
-                let mut args = Expression::evaluate_arguments(args, user_fns);
-                match func {
-                    "+" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Add(a, b))
-                        }
-                        _ => panic!("invalid add statement"),
-                    },
-                    "-" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Sub(a, b))
-                        }
-                        _ => panic!("invalid subtract statement"),
-                    },
-                    "/" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Div(a, b))
-                        }
-                        _ => panic!("invalid divide statement"),
-                    },
-                    "*" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Mul(a, b))
-                        }
-                        _ => panic!("invalid multiply statement"),
-                    },
-                    "%" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Mod(a, b))
-                        }
-                        _ => panic!("invalid multiply statement"),
-                    },
-                    "==" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Eq(a, b))
-                        }
-                        _ => panic!("invalid equals statement"),
-                    },
-                    "!=" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Neq(a, b))
-                        }
-                        _ => panic!("invalid not equals statement"),
-                    },
-                    ">" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Gt(a, b))
-                        }
-                        _ => panic!("invalid gt statement"),
-                    },
-                    "<" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Lt(a, b))
-                        }
-                        _ => panic!("invalid lt statement"),
-                    },
-                    ">=" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Ge(a, b))
-                        }
-                        _ => panic!("invalid ge statement"),
-                    },
-                    "<=" => match args.len() {
-                        2 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            Some(BuiltIns::Le(a, b))
-                        }
-                        _ => panic!("invalid le statement"),
-                    },
-                    "?" => match args.len() {
-                        3 => {
-                            let a = args.remove(0);
-                            let b = args.remove(0);
-                            let c = args.remove(0);
-                            Some(BuiltIns::Ternary(a, b, c))
-                        }
-                        _ => panic!("invalid ternary"),
-                    },
-                    "!" => match args.len() {
-                        1 => Some(BuiltIns::Not(args.remove(0))),
-                        _ => panic!("invalid not statement"),
-                    },
-                    "print" => return Some(BuiltIns::Print(args)),
-                    "printa" => return Some(BuiltIns::Printa(args)),
-                    _ => return None,
-                }
-            },
-            None => {
-                match line {
-                    "print" => Some(BuiltIns::Print(Vec::new())),
-                    "printa" => Some(BuiltIns::Printa(Vec::new())),
-                    _ => None
-                }
+    pub fn is_built_in(name: &str) -> bool {
+        matches!(name, "+" | "-" | "*" | "/" | "%" | "==" | "!=" | ">" | "<" | ">=" | "<=" | "min" | "max"
+            | "and" | "or" | "&" | "|" | "^" | "<<" | ">>" | "~"
+            | "?" | "!" | "arg" | "argc" | "print" | "printa" | "len" | "push" | "pop" | "slice"
+            | "get" | "set" | "has" | "del" | "keys" | "call" | "map" | "filter" | "reduce" | "fold" | "any"
+            | "all" | "sort_by" | "zip" | "enumerate" | "range" | "abs" | "sign" | "pow" | "powmod" | "isqrt" | "sqrt"
+            | "gcd" | "lcm" | "clamp" | "log" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan")
+    }
+
+    // the built ins that are only run for what they do, like `print`
+    pub fn gives_value(&self) -> bool {
+        !matches!(self, BuiltIns::Print(_) | BuiltIns::Printa(_) | BuiltIns::Push(..) | BuiltIns::Set(..) | BuiltIns::Del(..))
+    }
+
+    // build the built in called `name`, checking it has been given the right number of arguments
+    pub fn from_name(name: &str, args: Vec<Expression<'a>>) -> Result<BuiltIns<'a>, MyLangError> {
+        // arithmetic and comparisons take two or more arguments, while `-` can also take just one, as can min and max
+        let at_least = match name {
+            "+" | "*" | "==" | "!=" | ">" | "<" | ">=" | "<=" | "and" | "or" | "&" | "|" | "^" => Some(2),
+            "-" | "min" | "max" => Some(1),
+            _ => None,
+        };
+        if let Some(expected) = at_least {
+            if args.len() < expected {
+                return Err(ErrorKind::TooFewArguments {
+                    name: String::from(name),
+                    expected,
+                    found: args.len(),
+                }.into());
             }
+            let built_in = match name {
+                "+" => BuiltIns::Add(args),
+                "-" => BuiltIns::Sub(args),
+                "*" => BuiltIns::Mul(args),
+                "min" => BuiltIns::Min(args),
+                "max" => BuiltIns::Max(args),
+                "==" => BuiltIns::Eq(args),
+                "!=" => BuiltIns::Neq(args),
+                ">" => BuiltIns::Gt(args),
+                "<" => BuiltIns::Lt(args),
+                ">=" => BuiltIns::Ge(args),
+                "<=" => BuiltIns::Le(args),
+                "and" => BuiltIns::And(args),
+                "or" => BuiltIns::Or(args),
+                "&" => BuiltIns::BitAnd(args),
+                "|" => BuiltIns::BitOr(args),
+                _ => BuiltIns::BitXor(args),
+            };
+            return Ok(built_in);
         }
+
+        // print and printa take any number of arguments, call takes a function and any number of arguments to call it with
+        let expected = match name {
+            "/" | "%" | "<<" | ">>" | "push" | "has" | "del"
+            | "map" | "filter" | "reduce" | "any" | "all" | "sort_by" | "zip" | "range" | "pow" | "gcd" | "lcm" => 2,
+            "?" | "slice" | "get" | "set" | "fold" | "powmod" | "clamp" => 3,
+            "!" | "~" | "arg" | "len" | "pop" | "keys" | "enumerate" | "abs" | "sign" | "isqrt" | "sqrt" | "log"
+            | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" => 1,
+            "argc" => 0,
+            "print" => return Ok(BuiltIns::Print(args)),
+            "printa" => return Ok(BuiltIns::Printa(args)),
+            "call" if !args.is_empty() => {
+                let mut args = args.into_iter();
+                return Ok(BuiltIns::Call(args.next().unwrap(), args.collect()));
+            }
+            "call" => 1,
+            _ => return Err(ErrorKind::UndefinedFunction(String::from(name)).into()),
+        };
+
+        if args.len() != expected {
+            return Err(ErrorKind::Arity {
+                name: String::from(name),
+                expected,
+                found: args.len(),
+            }.into());
+        }
+
+        // arity has been checked above, so there are always enough arguments to take
+        let mut args = args.into_iter();
+        let mut arg = || args.next().unwrap();
+        let built_in = match name {
+            "/" => BuiltIns::Div(arg(), arg()),
+            "%" => BuiltIns::Mod(arg(), arg()),
+            "<<" => BuiltIns::Shl(arg(), arg()),
+            ">>" => BuiltIns::Shr(arg(), arg()),
+            "~" => BuiltIns::BitNot(arg()),
+            "?" => BuiltIns::Ternary(arg(), arg(), arg()),
+            "!" => BuiltIns::Not(arg()),
+            "arg" => BuiltIns::Arg(arg()),
+            "len" => BuiltIns::Len(arg()),
+            "push" => BuiltIns::Push(arg(), arg()),
+            "pop" => BuiltIns::Pop(arg()),
+            "slice" => BuiltIns::Slice(arg(), arg(), arg()),
+            "get" => BuiltIns::Get(arg(), arg(), arg()),
+            "set" => BuiltIns::Set(arg(), arg(), arg()),
+            "has" => BuiltIns::Has(arg(), arg()),
+            "del" => BuiltIns::Del(arg(), arg()),
+            "keys" => BuiltIns::Keys(arg()),
+            "map" => BuiltIns::Map(arg(), arg()),
+            "filter" => BuiltIns::Filter(arg(), arg()),
+            "reduce" => BuiltIns::Reduce(arg(), arg()),
+            "fold" => BuiltIns::Fold(arg(), arg(), arg()),
+            "any" => BuiltIns::Any(arg(), arg()),
+            "all" => BuiltIns::All(arg(), arg()),
+            "sort_by" => BuiltIns::SortBy(arg(), arg()),
+            "zip" => BuiltIns::Zip(arg(), arg()),
+            "enumerate" => BuiltIns::Enumerate(arg()),
+            "range" => BuiltIns::Range(arg(), arg()),
+            "abs" => BuiltIns::Abs(arg()),
+            "sign" => BuiltIns::Sign(arg()),
+            "pow" => BuiltIns::Pow(arg(), arg()),
+            "powmod" => BuiltIns::PowMod(arg(), arg(), arg()),
+            "isqrt" => BuiltIns::Isqrt(arg()),
+            "sqrt" => BuiltIns::Sqrt(arg()),
+            "gcd" => BuiltIns::Gcd(arg(), arg()),
+            "lcm" => BuiltIns::Lcm(arg(), arg()),
+            "clamp" => BuiltIns::Clamp(arg(), arg(), arg()),
+            "log" => BuiltIns::Log(arg()),
+            "sin" => BuiltIns::Sin(arg()),
+            "cos" => BuiltIns::Cos(arg()),
+            "tan" => BuiltIns::Tan(arg()),
+            "asin" => BuiltIns::Asin(arg()),
+            "acos" => BuiltIns::Acos(arg()),
+            "atan" => BuiltIns::Atan(arg()),
+            _ => BuiltIns::Argc,
+        };
+        Ok(built_in)
     }
 
-    pub fn apply(&self, data_store: &mut DataStore<'a>) -> Option<i64> {
+    // the name a built in is called by in the source
+    pub fn name(&self) -> &'static str {
         match self {
-            BuiltIns::Add(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(i + j)
-            }
+            BuiltIns::Add(..) => "+",
+            BuiltIns::Sub(..) => "-",
+            BuiltIns::Mul(..) => "*",
+            BuiltIns::Div(..) => "/",
+            BuiltIns::Mod(..) => "%",
+            BuiltIns::Min(..) => "min",
+            BuiltIns::Max(..) => "max",
+            BuiltIns::Eq(..) => "==",
+            BuiltIns::Neq(..) => "!=",
+            BuiltIns::Lt(..) => "<",
+            BuiltIns::Gt(..) => ">",
+            BuiltIns::Le(..) => "<=",
+            BuiltIns::Ge(..) => ">=",
+            BuiltIns::And(..) => "and",
+            BuiltIns::Or(..) => "or",
+            BuiltIns::BitAnd(..) => "&",
+            BuiltIns::BitOr(..) => "|",
+            BuiltIns::BitXor(..) => "^",
+            BuiltIns::Shl(..) => "<<",
+            BuiltIns::Shr(..) => ">>",
+            BuiltIns::BitNot(..) => "~",
+            BuiltIns::Ternary(..) => "?",
+            BuiltIns::Not(..) => "!",
+            BuiltIns::Print(..) => "print",
+            BuiltIns::Printa(..) => "printa",
+            BuiltIns::Argc => "argc",
+            BuiltIns::Arg(..) => "arg",
+            BuiltIns::Len(..) => "len",
+            BuiltIns::Push(..) => "push",
+            BuiltIns::Pop(..) => "pop",
+            BuiltIns::Slice(..) => "slice",
+            BuiltIns::Get(..) => "get",
+            BuiltIns::Set(..) => "set",
+            BuiltIns::Has(..) => "has",
+            BuiltIns::Del(..) => "del",
+            BuiltIns::Keys(..) => "keys",
+            BuiltIns::Call(..) => "call",
+            BuiltIns::Map(..) => "map",
+            BuiltIns::Filter(..) => "filter",
+            BuiltIns::Reduce(..) => "reduce",
+            BuiltIns::Fold(..) => "fold",
+            BuiltIns::Any(..) => "any",
+            BuiltIns::All(..) => "all",
+            BuiltIns::SortBy(..) => "sort_by",
+            BuiltIns::Zip(..) => "zip",
+            BuiltIns::Enumerate(..) => "enumerate",
+            BuiltIns::Range(..) => "range",
+            BuiltIns::Abs(..) => "abs",
+            BuiltIns::Sign(..) => "sign",
+            BuiltIns::Pow(..) => "pow",
+            BuiltIns::PowMod(..) => "powmod",
+            BuiltIns::Isqrt(..) => "isqrt",
+            BuiltIns::Sqrt(..) => "sqrt",
+            BuiltIns::Gcd(..) => "gcd",
+            BuiltIns::Lcm(..) => "lcm",
+            BuiltIns::Clamp(..) => "clamp",
+            BuiltIns::Log(..) => "log",
+            BuiltIns::Sin(..) => "sin",
+            BuiltIns::Cos(..) => "cos",
+            BuiltIns::Tan(..) => "tan",
+            BuiltIns::Asin(..) => "asin",
+            BuiltIns::Acos(..) => "acos",
+            BuiltIns::Atan(..) => "atan",
+        }
+    }
+
+    // print, printa, push, set and del are run for their effect and give back no value
+    pub fn apply(&self, data_store: &mut DataStore<'a>) -> Result<Option<Value<'a>>, MyLangError> {
+        let overflow = data_store.overflow();
+        let val = match self {
+            BuiltIns::Add(args) => self.fold(args, data_store, |i, j| match (&i, &j) {
+                (Value::Str(a), Value::Str(b)) => Ok(Value::Str(format!("{}{}", a, b).into())),
+                _ => self.arithmetic(i, j, &ADD, overflow),
+            })?,
             BuiltIns::Div(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(i / j)
-            }
-            BuiltIns::Mul(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(i * j)
+                let i = i.evaluate(data_store)?;
+                let j = j.evaluate(data_store)?;
+                if j == Value::Int(0) {
+                    return Err(ErrorKind::DivisionByZero.into());
+                }
+                self.arithmetic(i, j, &DIV, overflow)?
             }
-            BuiltIns::Sub(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(i - j)
+            BuiltIns::Mul(args) => self.fold(args, data_store, |i, j| self.arithmetic(i, j, &MUL, overflow))?,
+            BuiltIns::Sub(args) if args.len() == 1 => {
+                let i = args[0].evaluate(data_store)?;
+                match i {
+                    Value::Int(i) => overflow.narrow(-i128::from(i))?,
+                    Value::BigInt(n) => overflow.fit(-&*n)?,
+                    Value::Float(x) => Value::Float(-x),
+                    _ => return Err(type_error(self.name(), &[&i])),
+                }
             }
+            BuiltIns::Sub(args) => self.fold(args, data_store, |i, j| self.arithmetic(i, j, &SUB, overflow))?,
             BuiltIns::Mod(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(i % j)
-            }
-            BuiltIns::Eq(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(if i == j { 1 } else { 0 })
-            }
-            BuiltIns::Neq(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(if i == j { 0 } else { 1 })
-            }
-            BuiltIns::Gt(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(if i > j { 1 } else { 0 })
-            }
-            BuiltIns::Lt(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(if i < j { 1 } else { 0 })
-            }
-            BuiltIns::Ge(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(if i >= j { 1 } else { 0 })
-            }
-            BuiltIns::Le(i, j) => {
-                let i = i.evaluate(data_store).unwrap();
-                let j = j.evaluate(data_store).unwrap();
-                Some(if i <= j { 1 } else { 0 })
+                let i = i.evaluate(data_store)?;
+                let j = j.evaluate(data_store)?;
+                if j == Value::Int(0) {
+                    return Err(ErrorKind::DivisionByZero.into());
+                }
+                self.arithmetic(i, j, &REM, overflow)?
+            }
+            // a single list gives the least or greatest of its items. the first of any equal values is given back
+            BuiltIns::Min(args) | BuiltIns::Max(args) => {
+                let mut items = match args.as_slice() {
+                    [arg] => match arg.evaluate(data_store)? {
+                        Value::List(list) => list.borrow().clone(),
+                        val => vec![val],
+                    },
+                    args => args.iter()
+                        .map(|arg| arg.evaluate(data_store))
+                        .collect::<Result<_, MyLangError>>()?,
+                }.into_iter();
+                let want = if let BuiltIns::Min(_) = self { Ordering::Less } else { Ordering::Greater };
+                let mut best = items.next()
+                    .ok_or_else(|| ErrorKind::InvalidArgument(format!("can't take the {} of an empty list", self.name())))?;
+                for item in items {
+                    if self.compare(&item, &best)? == want {
+                        best = item;
+                    }
+                }
+                best
+            }
+            BuiltIns::Eq(args) => self.chain(args, data_store, |i, j| Ok(i == j))?,
+            BuiltIns::Neq(args) => self.chain(args, data_store, |i, j| Ok(i != j))?,
+            BuiltIns::Gt(args) => self.chain(args, data_store, |i, j| Ok(self.compare(i, j)? == Ordering::Greater))?,
+            BuiltIns::Lt(args) => self.chain(args, data_store, |i, j| Ok(self.compare(i, j)? == Ordering::Less))?,
+            BuiltIns::Ge(args) => self.chain(args, data_store, |i, j| Ok(self.compare(i, j)? != Ordering::Less))?,
+            BuiltIns::Le(args) => self.chain(args, data_store, |i, j| Ok(self.compare(i, j)? != Ordering::Greater))?,
+            BuiltIns::And(args) | BuiltIns::Or(args) => {
+                // the value that decides the result as soon as an argument has it
+                let decider = matches!(self, BuiltIns::Or(_));
+                for arg in args {
+                    if arg.evaluate(data_store)?.truthy(self.name())? == decider {
+                        return Ok(Some(Value::Bool(decider)));
+                    }
+                }
+                Value::Bool(!decider)
+            }
+            BuiltIns::BitAnd(args) => self.fold(args, data_store, |i, j| self.bitwise(i, j, |a, b| a & b))?,
+            BuiltIns::BitOr(args) => self.fold(args, data_store, |i, j| self.bitwise(i, j, |a, b| a | b))?,
+            BuiltIns::BitXor(args) => self.fold(args, data_store, |i, j| self.bitwise(i, j, |a, b| a ^ b))?,
+            // shifting by a negative amount isn't allowed. shifting left multiplies by a power of 2, so bits
+            // shifted out of an int are an overflow. shifting right divides, rounding down
+            BuiltIns::Shl(i, j) | BuiltIns::Shr(i, j) => {
+                let i = i.evaluate(data_store)?;
+                let j = j.evaluate(data_store)?;
+                let (a, bits) = match (&i, &j) {
+                    (Value::Int(a), Value::Int(b)) => (*a, u64::try_from(*b)
+                        .map_err(|_| ErrorKind::InvalidArgument(format!("can't shift by {} bits", b)))?),
+                    _ => return Err(self.int_error(&i, &j)),
+                };
+                match self {
+                    BuiltIns::Shl(..) => overflow.shl(a, bits)?,
+                    _ => Value::Int(a >> bits.min(63)),
+                }
             }
+            // `-n - 1` for an int of any size
+            BuiltIns::BitNot(i) => match i.evaluate(data_store)? {
+                Value::Int(i) => Value::Int(!i),
+                Value::BigInt(n) => overflow.fit(&-&*n - &BigInt::from(1i64))?,
+                i => return Err(type_error(self.name(), &[&i])),
+            },
             BuiltIns::Ternary(a, b, c) => {
-                let expr = a.evaluate(data_store).unwrap();
-                if expr != 0 {
-                    Some(b.evaluate(data_store).unwrap())
+                let expr = a.evaluate(data_store)?;
+                if expr.truthy(self.name())? {
+                    b.evaluate(data_store)?
                 }
                 else {
-                    Some(c.evaluate(data_store).unwrap())
+                    c.evaluate(data_store)?
                 }
             }
             BuiltIns::Not(i) => {
-                let i = i.evaluate(data_store).unwrap();
-                Some(if i != 0 { 1 } else { 0 })
+                let i = i.evaluate(data_store)?;
+                Value::Bool(!i.truthy(self.name())?)
             }
             BuiltIns::Print(args) => {
-                let expr_strings: Vec<String> = args.iter()
-                    .map(|v| v.evaluate(data_store).unwrap().to_string())
-                    .collect();
+                let expr_strings = args.iter()
+                    .map(|v| v.evaluate(data_store).map(|v| v.to_string()))
+                    .collect::<Result<Vec<String>, MyLangError>>()?;
                 println!("{}", expr_strings.join(" "));
-                None
+                return Ok(None);
             }
             BuiltIns::Printa(args) => {
-                let as_string: String = args.iter()
-                    .map(|v| std::char::from_u32(v.evaluate(data_store).unwrap() as u32).unwrap())
-                    .collect();
+                let as_string = args.iter()
+                    .map(|v| {
+                        let v = v.evaluate(data_store)?.as_int(self.name())?;
+                        u32::try_from(v).ok()
+                            .and_then(std::char::from_u32)
+                            .ok_or_else(|| ErrorKind::InvalidArgument(format!("{} is not a character", v)).into())
+                    })
+                    .collect::<Result<String, MyLangError>>()?;
                 println!("{}", as_string);
-                None
+                return Ok(None);
             }
-        }
-    }
-
-    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>) -> BuiltIns<'a> {
-        match self {
-            BuiltIns::Add(a, b) => BuiltIns::Add(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Sub(a, b) => BuiltIns::Sub(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Mul(a, b) => BuiltIns::Mul(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Div(a, b) => BuiltIns::Div(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Mod(a, b) => BuiltIns::Mod(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Eq(a, b) => BuiltIns::Eq(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Neq(a, b) => BuiltIns::Neq(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Lt(a, b) => BuiltIns::Lt(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Gt(a, b) => BuiltIns::Gt(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Le(a, b) => BuiltIns::Le(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Ge(a, b) => BuiltIns::Ge(a.optimise(user_fns), b.optimise(user_fns)),
-            BuiltIns::Ternary(a, b, c) => BuiltIns::Ternary(a.optimise(user_fns), b.optimise(user_fns), c.optimise(user_fns)),
-            BuiltIns::Not(a) => BuiltIns::Not(a.optimise(user_fns)),
-            BuiltIns::Print(args) => {
+            BuiltIns::Argc => Value::Int(data_store.args().len() as i64),
+            // arguments that look like numbers are given back as them, anything else as a string
+            BuiltIns::Arg(i) => {
+                let i = i.evaluate(data_store)?.as_int(self.name())?;
+                let arg = usize::try_from(i).ok()
+                    .and_then(|i| data_store.args().get(i))
+                    .ok_or_else(|| ErrorKind::InvalidArgument(format!("no script argument {}", i)))?;
+                if let Ok(int) = arg.parse() {
+                    Value::Int(int)
+                } else if let Some(int) = BigInt::parse(arg) {
+                    Value::big(int)
+                } else if let Ok(float) = arg.parse() {
+                    Value::Float(float)
+                } else {
+                    Value::Str(Rc::new(arg.clone()))
+                }
+            }
+            // strings are measured in characters
+            BuiltIns::Len(list) => {
+                let list = list.evaluate(data_store)?;
+                match &list {
+                    Value::List(items) => Value::Int(items.borrow().len() as i64),
+                    Value::Map(map) => Value::Int(map.borrow().len() as i64),
+                    Value::Str(s) => Value::Int(s.chars().count() as i64),
+                    _ => return Err(type_error(self.name(), &[&list])),
+                }
+            }
+            BuiltIns::Push(list, item) => {
+                let list = list.evaluate(data_store)?;
+                let item = item.evaluate(data_store)?;
+                list.as_list(self.name())?.borrow_mut().push(item);
+                return Ok(None);
+            }
+            BuiltIns::Pop(list) => {
+                let list = list.evaluate(data_store)?;
+                let item = list.as_list(self.name())?.borrow_mut().pop();
+                item.ok_or_else(|| ErrorKind::InvalidArgument(String::from("can't pop from an empty list")))?
+            }
+            // a new list of the items from `start` up to but not including `end`
+            BuiltIns::Slice(list, start, end) => {
+                let list = list.evaluate(data_store)?;
+                let start = start.evaluate(data_store)?.as_int(self.name())?;
+                let end = end.evaluate(data_store)?.as_int(self.name())?;
+                let items = list.as_list(self.name())?.borrow();
+                let range = usize::try_from(start).ok()
+                    .zip(usize::try_from(end).ok())
+                    .filter(|&(start, end)| start <= end && end <= items.len())
+                    .ok_or_else(|| ErrorKind::InvalidArgument(format!("can't slice {} to {} from a list of length {}", start, end, items.len())))?;
+                Value::list(items[range.0..range.1].to_vec())
+            }
+            // the value for a key, or `default` if the map doesn't have it
+            BuiltIns::Get(map, key, default) => {
+                let map = map.evaluate(data_store)?;
+                let key = key.evaluate(data_store)?.as_key(self.name())?;
+                let val = map.as_map(self.name())?.borrow().get(&key).cloned();
+                match val {
+                    Some(val) => val,
+                    None => default.evaluate(data_store)?,
+                }
+            }
+            BuiltIns::Set(map, key, val) => {
+                let map = map.evaluate(data_store)?;
+                let key = key.evaluate(data_store)?.as_key(self.name())?;
+                let val = val.evaluate(data_store)?;
+                map.as_map(self.name())?.borrow_mut().insert(key, val);
+                return Ok(None);
+            }
+            BuiltIns::Has(map, key) => {
+                let map = map.evaluate(data_store)?;
+                let key = key.evaluate(data_store)?.as_key(self.name())?;
+                let has = map.as_map(self.name())?.borrow().contains_key(&key);
+                Value::Bool(has)
+            }
+            // removing a key that isn't there does nothing
+            BuiltIns::Del(map, key) => {
+                let map = map.evaluate(data_store)?;
+                let key = key.evaluate(data_store)?.as_key(self.name())?;
+                map.as_map(self.name())?.borrow_mut().remove(&key);
+                return Ok(None);
+            }
+            // a list of the keys in order
+            BuiltIns::Keys(map) => {
+                let map = map.evaluate(data_store)?;
+                Value::list(sorted_keys(map.as_map(self.name())?))
+            }
+            // the only way to call a function value that takes no arguments
+            BuiltIns::Call(func, args) => {
+                let span = func.span();
+                let func = func.evaluate(data_store)?;
+                let func = func.as_function(self.name())?;
                 let args = args.iter()
-                    .map(|arg| arg.optimise(user_fns))
+                    .map(|arg| arg.evaluate(data_store))
+                    .collect::<Result<_, MyLangError>>()?;
+                func.call(args, data_store, span)?
+            }
+            // the higher order built ins take a function and then a list, or a map to work over its keys in order.
+            // they work over the items the list had when they were called
+            BuiltIns::Map(func, items) => {
+                let (func, span, items) = self.function_and_items(func, items, data_store)?;
+                let mapped = items.into_iter()
+                    .map(|item| func.call(vec![item], data_store, span))
+                    .collect::<Result<_, MyLangError>>()?;
+                Value::list(mapped)
+            }
+            BuiltIns::Filter(func, items) => {
+                let (func, span, items) = self.function_and_items(func, items, data_store)?;
+                let mut kept = Vec::new();
+                for item in items {
+                    if func.call(vec![item.clone()], data_store, span)?.truthy(self.name())? {
+                        kept.push(item);
+                    }
+                }
+                Value::list(kept)
+            }
+            // combines the items from the left, starting from the first one
+            BuiltIns::Reduce(func, items) => {
+                let (func, span, items) = self.function_and_items(func, items, data_store)?;
+                let mut items = items.into_iter();
+                let first = items.next()
+                    .ok_or_else(|| ErrorKind::InvalidArgument(String::from("can't reduce an empty list")))?;
+                items.try_fold(first, |acc, item| func.call(vec![acc, item], data_store, span))?
+            }
+            // combines the items from the left, starting from `init`
+            BuiltIns::Fold(func, init, items) => {
+                let init = init.evaluate(data_store)?;
+                let (func, span, items) = self.function_and_items(func, items, data_store)?;
+                items.into_iter().try_fold(init, |acc, item| func.call(vec![acc, item], data_store, span))?
+            }
+            // both stop at the first item that settles the answer
+            BuiltIns::Any(func, items) | BuiltIns::All(func, items) => {
+                let any = matches!(self, BuiltIns::Any(..));
+                let (func, span, items) = self.function_and_items(func, items, data_store)?;
+                for item in items {
+                    if func.call(vec![item], data_store, span)?.truthy(self.name())? == any {
+                        return Ok(Some(Value::Bool(any)));
+                    }
+                }
+                Value::Bool(!any)
+            }
+            // a new list in the order given by `func`, which says whether its first argument goes before its
+            // second. items it doesn't put in order keep the order they had
+            BuiltIns::SortBy(func, items) => {
+                let (func, span, items) = self.function_and_items(func, items, data_store)?;
+                let mut less = |a: &Value<'a>, b: &Value<'a>| {
+                    func.call(vec![a.clone(), b.clone()], data_store, span)?.truthy(self.name())
+                };
+                Value::list(merge_sort(items, &mut less)?)
+            }
+            // pairs of items from the two lists, as many as the shorter one has
+            BuiltIns::Zip(a, b) => {
+                let a = a.evaluate(data_store)?.items(self.name())?;
+                let b = b.evaluate(data_store)?.items(self.name())?;
+                let pairs = a.into_iter()
+                    .zip(b)
+                    .map(|(a, b)| Value::list(vec![a, b]))
                     .collect();
-                BuiltIns::Print(args)
+                Value::list(pairs)
             }
-            BuiltIns::Printa(args) => {
-                let args = args.iter()
-                    .map(|arg| arg.optimise(user_fns))
+            // pairs of each item's index and the item
+            BuiltIns::Enumerate(items) => {
+                let items = items.evaluate(data_store)?.items(self.name())?;
+                let pairs = items.into_iter()
+                    .enumerate()
+                    .map(|(i, item)| Value::list(vec![Value::Int(i as i64), item]))
                     .collect();
-                BuiltIns::Printa(args)
+                Value::list(pairs)
+            }
+            // the ints from `start` up to but not including `end`
+            BuiltIns::Range(start, end) => {
+                let start = start.evaluate(data_store)?.as_int(self.name())?;
+                let end = end.evaluate(data_store)?.as_int(self.name())?;
+                Value::list((start..end).map(Value::Int).collect())
+            }
+            BuiltIns::Abs(x) => match x.evaluate(data_store)? {
+                Value::Int(i) => overflow.narrow(i128::from(i).abs())?,
+                Value::BigInt(n) => overflow.fit(n.abs())?,
+                x => Value::Float(x.as_float(self.name())?.abs()),
+            },
+            // -1, 0 or 1
+            BuiltIns::Sign(x) => match x.evaluate(data_store)? {
+                Value::Int(i) => Value::Int(i.signum()),
+                Value::BigInt(n) => Value::Int(n.signum()),
+                x => {
+                    let x = x.as_float(self.name())?;
+                    Value::Int(if x > 0.0 { 1 } else if x < 0.0 { -1 } else { 0 })
+                }
+            },
+            // an int raised to a power that isn't negative stays an int
+            BuiltIns::Pow(x, y) => {
+                let x = x.evaluate(data_store)?;
+                let y = y.evaluate(data_store)?;
+                match (&x, &y) {
+                    (Value::Int(a), Value::Int(b)) if *b >= 0 => overflow.pow(*a, *b as u64)?,
+                    (Value::BigInt(a), Value::Int(b)) if *b >= 0 => overflow.pow_big(a, *b as u64)?,
+                    _ => {
+                        let (a, b) = (x.as_float(self.name())?, y.as_float(self.name())?);
+                        self.float_result(a.powf(b), &[a, b])?
+                    }
+                }
+            }
+            // `base` to the power of `exp`, modulo `modulus`, without the power itself ever being worked out
+            BuiltIns::PowMod(base, exp, modulus) => {
+                let base = base.evaluate(data_store)?;
+                let exp = exp.evaluate(data_store)?;
+                let modulus = modulus.evaluate(data_store)?;
+                base.int_signum(self.name())?;
+                if exp.int_signum(self.name())? < 0 || modulus.int_signum(self.name())? <= 0 {
+                    return Err(ErrorKind::InvalidArgument(format!("powmod needs an exponent of at least 0 and a modulus of at least 1, not {} and {}", exp, modulus)).into());
+                }
+                match (&base, &exp, &modulus) {
+                    (Value::Int(base), Value::Int(exp), Value::Int(modulus)) => Value::Int(pow_mod(*base, *exp, *modulus)),
+                    _ => overflow.fit(big(&base).pow_mod(&big(&exp), &big(&modulus)))?,
+                }
+            }
+            // the square root of an int rounded down
+            BuiltIns::Isqrt(n) => {
+                let n = n.evaluate(data_store)?;
+                if n.int_signum(self.name())? < 0 {
+                    return Err(ErrorKind::InvalidArgument(format!("can't take the square root of {}", n)).into());
+                }
+                match n {
+                    Value::Int(n) => Value::Int(isqrt(n)),
+                    n => overflow.fit(big(&n).isqrt())?,
+                }
+            }
+            BuiltIns::Sqrt(x) => self.float_function(x, f64::sqrt, data_store)?,
+            BuiltIns::Gcd(a, b) => {
+                let a = a.evaluate(data_store)?;
+                let b = b.evaluate(data_store)?;
+                match (&a, &b) {
+                    (Value::Int(a), Value::Int(b)) => overflow.narrow(gcd(*a, *b).into())?,
+                    _ => {
+                        let (a, b) = self.big_ints(&a, &b)?;
+                        overflow.fit(a.gcd(&b))?
+                    }
+                }
+            }
+            BuiltIns::Lcm(a, b) => {
+                let a = a.evaluate(data_store)?;
+                let b = b.evaluate(data_store)?;
+                match (&a, &b) {
+                    (Value::Int(a), Value::Int(b)) => {
+                        let lcm = match gcd(*a, *b) {
+                            0 => 0,
+                            gcd => i128::from(a.unsigned_abs() / gcd) * i128::from(b.unsigned_abs()),
+                        };
+                        overflow.narrow(lcm)?
+                    }
+                    _ => {
+                        let (a, b) = self.big_ints(&a, &b)?;
+                        let gcd = a.gcd(&b);
+                        match gcd.signum() {
+                            0 => Value::Int(0),
+                            _ => overflow.fit(&(&a.abs() / &gcd) * &b.abs())?,
+                        }
+                    }
+                }
+            }
+            // `x` if it's between `low` and `high`, otherwise whichever of them it's closest to
+            BuiltIns::Clamp(x, low, high) => {
+                let x = x.evaluate(data_store)?;
+                let low = low.evaluate(data_store)?;
+                let high = high.evaluate(data_store)?;
+                if self.compare(&low, &high)? == Ordering::Greater {
+                    return Err(ErrorKind::InvalidArgument(format!("can't clamp between {} and {}", low, high)).into());
+                }
+                if self.compare(&x, &low)? == Ordering::Less {
+                    low
+                } else if self.compare(&x, &high)? == Ordering::Greater {
+                    high
+                } else {
+                    x
+                }
+            }
+            // the natural logarithm
+            BuiltIns::Log(x) => self.float_function(x, f64::ln, data_store)?,
+            // angles are in radians
+            BuiltIns::Sin(x) => self.float_function(x, f64::sin, data_store)?,
+            BuiltIns::Cos(x) => self.float_function(x, f64::cos, data_store)?,
+            BuiltIns::Tan(x) => self.float_function(x, f64::tan, data_store)?,
+            BuiltIns::Asin(x) => self.float_function(x, f64::asin, data_store)?,
+            BuiltIns::Acos(x) => self.float_function(x, f64::acos, data_store)?,
+            BuiltIns::Atan(x) => self.float_function(x, f64::atan, data_store)?,
+        };
+        Ok(Some(val))
+    }
+
+    fn float_function(&self, x: &Expression<'a>, f: fn(f64) -> f64, data_store: &mut DataStore<'a>) -> Result<Value<'a>, MyLangError> {
+        let x = x.evaluate(data_store)?.as_float(self.name())?;
+        self.float_result(f(x), &[x])
+    }
+
+    // a float that isn't a number or is infinite means the function was given something outside of what
+    // it can work with, like the square root of a negative number
+    fn float_result(&self, result: f64, args: &[f64]) -> Result<Value<'a>, MyLangError> {
+        if result.is_finite() {
+            return Ok(Value::Float(result));
+        }
+        let args = args.iter().map(|arg| Value::Float(*arg).to_string()).collect::<Vec<_>>();
+        Err(ErrorKind::InvalidArgument(format!("{} can't be given {}", self.name(), args.join(" and "))).into())
+    }
+
+    // two ints give an int, with the overflow policy deciding what happens if it doesn't fit. an int and a
+    // float, or two floats, give a float. big ints are only used once an int has overflowed, so ints that
+    // fit in an `Int` stay on the fast path
+    #[inline]
+    fn arithmetic(&self, i: Value<'a>, j: Value<'a>, op: &Arithmetic, overflow: Overflow) -> Result<Value<'a>, MyLangError> {
+        match (&i, &j) {
+            (Value::Int(a), Value::Int(b)) => overflow.apply(op, *a, *b),
+            (Value::Int(a), Value::Float(b)) => Ok(Value::Float((op.floats)(*a as f64, *b))),
+            (Value::Float(a), Value::Int(b)) => Ok(Value::Float((op.floats)(*a, *b as f64))),
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float((op.floats)(*a, *b))),
+            (Value::BigInt(_), Value::Int(_)) | (Value::Int(_), Value::BigInt(_)) | (Value::BigInt(_), Value::BigInt(_)) => {
+                overflow.fit((op.big)(&i.to_big().unwrap(), &j.to_big().unwrap()))
+            }
+            (Value::BigInt(a), Value::Float(b)) => Ok(Value::Float((op.floats)(a.to_f64(), *b))),
+            (Value::Float(a), Value::BigInt(b)) => Ok(Value::Float((op.floats)(*a, b.to_f64()))),
+            _ => Err(type_error(self.name(), &[&i, &j])),
+        }
+    }
+
+    // two ints give an int
+    fn bitwise(&self, i: Value<'a>, j: Value<'a>, ints: fn(i64, i64) -> i64) -> Result<Value<'a>, MyLangError> {
+        match (&i, &j) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(ints(*a, *b))),
+            _ => Err(self.int_error(&i, &j)),
+        }
+    }
+
+    // what's wrong with the arguments of a built in that only takes ints that fit in 64 bits
+    fn int_error(&self, i: &Value<'a>, j: &Value<'a>) -> MyLangError {
+        match (i, j) {
+            (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => ErrorKind::BigIntUnsupported(String::from(self.name())).into(),
+            _ => type_error(self.name(), &[i, j]),
+        }
+    }
+
+    // two ints of any size
+    fn big_ints(&self, i: &Value<'a>, j: &Value<'a>) -> Result<(BigInt, BigInt), MyLangError> {
+        match (i.to_big(), j.to_big()) {
+            (Some(a), Some(b)) => Ok((a, b)),
+            _ => Err(type_error(self.name(), &[i, j])),
+        }
+    }
+
+    // the function a higher order built in was given, where it was given it, and the items it's to work over
+    fn function_and_items(&self, func: &Expression<'a>, items: &Expression<'a>, data_store: &mut DataStore<'a>) -> Result<(Rc<Closure<'a>>, Span, Vec<Value<'a>>), MyLangError> {
+        let span = func.span();
+        let func = Rc::clone(func.evaluate(data_store)?.as_function(self.name())?);
+        let items = items.evaluate(data_store)?.items(self.name())?;
+        Ok((func, span, items))
+    }
+
+    fn compare(&self, i: &Value<'a>, j: &Value<'a>) -> Result<Ordering, MyLangError> {
+        i.compare(j).ok_or_else(|| type_error(self.name(), &[i, j]))
+    }
+
+    // the result of combining the first argument with the second, then that with the third, and so on
+    #[inline]
+    fn fold(&self, args: &[Expression<'a>], data_store: &mut DataStore<'a>, combine: impl Fn(Value<'a>, Value<'a>) -> Result<Value<'a>, MyLangError>) -> Result<Value<'a>, MyLangError> {
+        if let [i, j] = args {
+            let i = i.evaluate(data_store)?;
+            return combine(i, j.evaluate(data_store)?);
+        }
+        let mut result = args[0].evaluate(data_store)?;
+        for arg in &args[1..] {
+            result = combine(result, arg.evaluate(data_store)?)?;
+        }
+        Ok(result)
+    }
+
+    // whether each argument and the one after it are related by `holds`. stops at the first pair that
+    // aren't, without working out the arguments after it
+    #[inline]
+    fn chain(&self, args: &[Expression<'a>], data_store: &mut DataStore<'a>, holds: impl Fn(&Value<'a>, &Value<'a>) -> Result<bool, MyLangError>) -> Result<Value<'a>, MyLangError> {
+        if let [i, j] = args {
+            let i = i.evaluate(data_store)?;
+            let j = j.evaluate(data_store)?;
+            return Ok(Value::Bool(holds(&i, &j)?));
+        }
+        let mut i = args[0].evaluate(data_store)?;
+        for arg in &args[1..] {
+            let j = arg.evaluate(data_store)?;
+            if !holds(&i, &j)? {
+                return Ok(Value::Bool(false));
             }
+            i = j;
+        }
+        Ok(Value::Bool(true))
+    }
+
+    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &mut Scope<'a>) -> Result<BuiltIns<'a>, MyLangError> {
+        let built_in = match self {
+            BuiltIns::Add(args) => BuiltIns::Add(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Sub(args) => BuiltIns::Sub(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Mul(args) => BuiltIns::Mul(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Div(a, b) => BuiltIns::Div(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Mod(a, b) => BuiltIns::Mod(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Min(args) => BuiltIns::Min(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Max(args) => BuiltIns::Max(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Eq(args) => BuiltIns::Eq(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Neq(args) => BuiltIns::Neq(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Lt(args) => BuiltIns::Lt(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Gt(args) => BuiltIns::Gt(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Le(args) => BuiltIns::Le(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Ge(args) => BuiltIns::Ge(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::And(args) => BuiltIns::And(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Or(args) => BuiltIns::Or(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::BitAnd(args) => BuiltIns::BitAnd(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::BitOr(args) => BuiltIns::BitOr(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::BitXor(args) => BuiltIns::BitXor(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Shl(a, b) => BuiltIns::Shl(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Shr(a, b) => BuiltIns::Shr(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::BitNot(a) => BuiltIns::BitNot(a.optimise(user_fns, scope)?),
+            BuiltIns::Ternary(a, b, c) => BuiltIns::Ternary(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?, c.optimise(user_fns, scope)?),
+            BuiltIns::Not(a) => BuiltIns::Not(a.optimise(user_fns, scope)?),
+            BuiltIns::Print(args) => BuiltIns::Print(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Printa(args) => BuiltIns::Printa(Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Argc => BuiltIns::Argc,
+            BuiltIns::Arg(a) => BuiltIns::Arg(a.optimise(user_fns, scope)?),
+            BuiltIns::Len(a) => BuiltIns::Len(a.optimise(user_fns, scope)?),
+            BuiltIns::Push(a, b) => BuiltIns::Push(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Pop(a) => BuiltIns::Pop(a.optimise(user_fns, scope)?),
+            BuiltIns::Slice(a, b, c) => BuiltIns::Slice(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?, c.optimise(user_fns, scope)?),
+            BuiltIns::Get(a, b, c) => BuiltIns::Get(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?, c.optimise(user_fns, scope)?),
+            BuiltIns::Set(a, b, c) => BuiltIns::Set(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?, c.optimise(user_fns, scope)?),
+            BuiltIns::Has(a, b) => BuiltIns::Has(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Del(a, b) => BuiltIns::Del(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Keys(a) => BuiltIns::Keys(a.optimise(user_fns, scope)?),
+            BuiltIns::Call(a, args) => BuiltIns::Call(a.optimise(user_fns, scope)?, Expression::optimise_all(args, user_fns, scope)?),
+            BuiltIns::Map(a, b) => BuiltIns::Map(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Filter(a, b) => BuiltIns::Filter(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Reduce(a, b) => BuiltIns::Reduce(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Fold(a, b, c) => BuiltIns::Fold(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?, c.optimise(user_fns, scope)?),
+            BuiltIns::Any(a, b) => BuiltIns::Any(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::All(a, b) => BuiltIns::All(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::SortBy(a, b) => BuiltIns::SortBy(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Zip(a, b) => BuiltIns::Zip(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Enumerate(a) => BuiltIns::Enumerate(a.optimise(user_fns, scope)?),
+            BuiltIns::Range(a, b) => BuiltIns::Range(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Abs(a) => BuiltIns::Abs(a.optimise(user_fns, scope)?),
+            BuiltIns::Sign(a) => BuiltIns::Sign(a.optimise(user_fns, scope)?),
+            BuiltIns::Pow(a, b) => BuiltIns::Pow(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::PowMod(a, b, c) => BuiltIns::PowMod(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?, c.optimise(user_fns, scope)?),
+            BuiltIns::Isqrt(a) => BuiltIns::Isqrt(a.optimise(user_fns, scope)?),
+            BuiltIns::Sqrt(a) => BuiltIns::Sqrt(a.optimise(user_fns, scope)?),
+            BuiltIns::Gcd(a, b) => BuiltIns::Gcd(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Lcm(a, b) => BuiltIns::Lcm(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?),
+            BuiltIns::Clamp(a, b, c) => BuiltIns::Clamp(a.optimise(user_fns, scope)?, b.optimise(user_fns, scope)?, c.optimise(user_fns, scope)?),
+            BuiltIns::Log(a) => BuiltIns::Log(a.optimise(user_fns, scope)?),
+            BuiltIns::Sin(a) => BuiltIns::Sin(a.optimise(user_fns, scope)?),
+            BuiltIns::Cos(a) => BuiltIns::Cos(a.optimise(user_fns, scope)?),
+            BuiltIns::Tan(a) => BuiltIns::Tan(a.optimise(user_fns, scope)?),
+            BuiltIns::Asin(a) => BuiltIns::Asin(a.optimise(user_fns, scope)?),
+            BuiltIns::Acos(a) => BuiltIns::Acos(a.optimise(user_fns, scope)?),
+            BuiltIns::Atan(a) => BuiltIns::Atan(a.optimise(user_fns, scope)?),
+        };
+        Ok(built_in)
+    }
+}
+
+// a stable merge sort. the comparison is a user function, which can fail, and which may not order the items
+// consistently, so the standard library's sort can't be used
+fn merge_sort<'a>(mut items: Vec<Value<'a>>, less: &mut impl FnMut(&Value<'a>, &Value<'a>) -> Result<bool, MyLangError>) -> Result<Vec<Value<'a>>, MyLangError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, less)?.into_iter().peekable();
+    let mut right = merge_sort(right, less)?.into_iter().peekable();
+    let mut sorted = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // an item from the right only goes first if it's strictly less, which keeps the sort stable
+        let from_right = less(r, l)?;
+        let item = if from_right { right.next() } else { left.next() };
+        sorted.extend(item);
+    }
+    sorted.extend(left);
+    sorted.extend(right);
+    Ok(sorted)
+}
+
+// an int of any size that's already been checked to be one
+fn big(n: &Value) -> BigInt {
+    n.to_big().unwrap()
+}
+
+// the greatest common divisor of `a` and `b`, which is only 0 if they both are. it's unsigned as the gcd of
+// `i64::MIN` and 0 doesn't fit in an i64
+fn gcd(a: i64, b: i64) -> u64 {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn isqrt(n: i64) -> i64 {
+    // the float square root is close, but can be off by one either way for large numbers
+    let mut root = (n as f64).sqrt() as i64;
+    while root.checked_mul(root).is_none_or(|square| square > n) {
+        root -= 1;
+    }
+    while (root + 1).checked_mul(root + 1).is_some_and(|square| square <= n) {
+        root += 1;
+    }
+    root
+}
+
+// the result is always between 0 and `modulus`, even for a negative base
+fn pow_mod(base: i64, mut exp: i64, modulus: i64) -> i64 {
+    let modulus = i128::from(modulus);
+    let mut base = i128::from(base).rem_euclid(modulus);
+    let mut result = 1 % modulus;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % modulus;
         }
+        base = base * base % modulus;
+        exp >>= 1;
+    }
+    result as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::program::tests::run;
+    use crate::{ErrorKind, Span};
+
+    fn error(text: &str) -> (ErrorKind, Option<Span>) {
+        let error = run(text).unwrap_err();
+        (error.kind().clone(), error.span())
+    }
+
+    #[test]
+    fn maps_are_read_and_changed_by_key() {
+        let globals = run("
+            m: [1: \"a\" \"b\": 2]
+            set m 3 \"c\"
+            del m 1
+            del m \"missing\"
+            m[\"z\"]: m[\"b\"]
+            a: [(get m 1 \"none\") (get m \"b\" 0) (has m 3) (has m 1) (len m)]
+            e: [:]
+        ").unwrap();
+        assert_eq!(globals, ["[3: \"c\" \"b\": 2 \"z\": 2]", "[\"none\" 2 true false 3]", "[:]"]);
+    }
+
+    #[test]
+    fn a_map_is_looped_over_by_its_keys_in_order() {
+        let globals = run("
+            m: [\"b\": 1 2: 2 \"a\": 3 1: 4]
+            seen: []
+            for k in m {
+                push seen k
+                set m (+ k k) 0
+            }
+            k: keys m
+        ").unwrap();
+        assert_eq!(globals[1], "[1 2 \"a\" \"b\"]");
+        assert_eq!(globals[2], "[1 2 4 \"a\" \"aa\" \"b\" \"bb\"]");
+    }
+
+    #[test]
+    fn keys_must_be_ints_or_strings() {
+        assert_eq!(error("m: [1.5: 2]"), (ErrorKind::Type { name: String::from("map"), found: String::from("float") }, Some(Span { file: 0, line: 1, column: 4, len: 3 })));
+        assert_eq!(error("m: [:]\nset m [1] 2").0, ErrorKind::Type { name: String::from("set"), found: String::from("list") });
+    }
+
+    #[test]
+    fn a_missing_key_or_a_map_built_in_on_a_list_is_an_error() {
+        assert_eq!(error("m: [1: 2]\nx: m[5]"), (ErrorKind::MissingKey(String::from("5")), Some(Span { file: 0, line: 2, column: 3, len: 4 })));
+        assert_eq!(error("x: get [1 2] 1 0").0, ErrorKind::Type { name: String::from("get"), found: String::from("list") });
+        assert!(matches!(error("m: [1: 2 3]").0, ErrorKind::Expected { .. }));
+    }
+
+    #[test]
+    fn arithmetic_takes_any_number_of_arguments_from_the_left() {
+        let globals = run("x: [(+ 1 2 3 4) (* 2 3 4) (- 10 1 2) (+ \"a\" \"b\" \"c\") (+ 1 2.5 1) (- 5) (- 2.5)]").unwrap();
+        assert_eq!(globals[0], "[10 24 7 \"abc\" 4.5 -5 -2.5]");
+    }
+
+    #[test]
+    fn min_and_max_take_any_number_of_arguments_or_a_list() {
+        let globals = run("x: [(min 3 1 2) (max 3 1 2) (min [4 2 8]) (max [4]) (min 2 1.5)]").unwrap();
+        assert_eq!(globals[0], "[1 3 2 4 1.5]");
+        assert_eq!(error("x: min []").0, ErrorKind::InvalidArgument(String::from("can't take the min of an empty list")));
+        assert_eq!(error("x: min 1 \"a\"").0, ErrorKind::Type { name: String::from("min"), found: String::from("str and int") });
+    }
+
+    #[test]
+    fn comparisons_of_more_than_two_arguments_are_chained() {
+        let globals = run("x: [(< 1 2 3) (< 1 3 2) (<= 1 1 2) (> 3 2 2) (== 2 2 2) (!= 1 2 1)]").unwrap();
+        assert_eq!(globals[0], "[true false true false true true]");
+    }
+
+    #[test]
+    fn a_chain_stops_at_the_first_comparison_that_fails() {
+        let globals = run("
+            seen: []
+            func see n {
+                push seen n
+                res: n
+            }
+            x: < (see 1) (see 3) (see 2) (see 4)
+        ").unwrap();
+        assert_eq!(globals, ["[1 3 2]", "false"]);
+    }
+
+    #[test]
+    fn too_few_arguments_is_an_error() {
+        let too_few = |name: &str, expected, found| ErrorKind::TooFewArguments { name: String::from(name), expected, found };
+        assert_eq!(error("x: + 1"), (too_few("+", 2, 1), Some(Span { file: 0, line: 1, column: 3, len: 3 })));
+        assert_eq!(error("x: < 1").0, too_few("<", 2, 1));
+        assert_eq!(error("x: min").0, too_few("min", 1, 0));
+        assert_eq!(error("x: - \"a\"").0, ErrorKind::Type { name: String::from("-"), found: String::from("str") });
+    }
+
+    #[test]
+    fn and_and_or_stop_at_the_first_argument_that_decides_them() {
+        let globals = run("
+            seen: []
+            func see b {
+                push seen b
+                res: b
+            }
+            x: [(and (see true) (see false) (see true)) (or (see false) (see true) (see false)) (and true 1)]
+        ").unwrap();
+        assert_eq!(globals, ["[true false false true]", "[false true true]"]);
+        assert_eq!(error("x: and 1 \"a\"").0, ErrorKind::Type { name: String::from("and"), found: String::from("str") });
+        assert_eq!(error("x: or true").0, ErrorKind::TooFewArguments { name: String::from("or"), expected: 2, found: 1 });
+    }
+
+    #[test]
+    fn bitwise_operators_work_on_the_bits_of_ints() {
+        let globals = run("x: [(& 12 10) (| 12 10) (^ 12 10) (~ 0) (<< 1 4) (>> 256 4) (>> -8 1) (>> 1 64) (>> -1 1000)]").unwrap();
+        assert_eq!(globals[0], "[8 14 6 -1 16 16 -4 0 -1]");
+    }
+
+    #[test]
+    fn bitwise_operators_only_take_ints_that_fit_in_64_bits() {
+        assert_eq!(error("x: & 1 2.5"), (ErrorKind::Type { name: String::from("&"), found: String::from("int and float") }, Some(Span { file: 0, line: 1, column: 3, len: 7 })));
+        assert_eq!(error("x: ~ 1.5").0, ErrorKind::Type { name: String::from("~"), found: String::from("float") });
+        assert_eq!(error("x: | (pow 2 70) 1").0, ErrorKind::BigIntUnsupported(String::from("|")));
+        assert_eq!(error("x: << 1 -1").0, ErrorKind::InvalidArgument(String::from("can't shift by -1 bits")));
     }
 }
@@ -1,95 +1,314 @@
-use core::slice::Iter;
-
-use regex::Regex;
-
-use crate::lib::{DataStore, Expression, Program, get_sub_program};
-use crate::lib::user_function::UserFunction;
+use crate::{DataStore, ErrorKind, Expression, Flow, MyLangError, Program, Scope, Span, StructType, Value, Variable};
+use crate::user_function::UserFunction;
+use crate::value::sorted_keys;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum Construct<'a> {
-    If(Expression<'a>, Program<'a>),
-    While(Expression<'a>, Program<'a>),
-    For(&'a str, Expression<'a>, Expression<'a>, Program<'a>),
+    // each condition is checked in turn and only the first that holds has its program run. if none
+    // do, the else program is run if there is one
+    If(Vec<(Expression<'a>, Program<'a>)>, Option<Program<'a>>, Span),
+    While(Expression<'a>, Program<'a>, Span),
+    For(Variable<'a>, Expression<'a>, Expression<'a>, Program<'a>, Span),
+    // `for VAR in LIST` or `for VAR in MAP`. items added to a list while looping over it are looped over too.
+    // a map's keys are looped over in order, and only those it had when the loop started
+    ForEach(Variable<'a>, Expression<'a>, Program<'a>, Span),
+    // only the program of the first arm whose pattern matches the value is run. if none do, the else program
+    // is run if there is one, otherwise it's an error
+    Match(Expression<'a>, Vec<(Pattern<'a>, Program<'a>)>, Option<Program<'a>>, Span),
 }
 
-impl<'a> Construct<'a> {
-    // check if a line parses as a construct of if/while/for statements. build up the construct if possible, else move on
-    pub fn parse(construct: &'a str, lines: &mut Iter<&'a str>, user_fns: &mut HashMap<&'a str, UserFunction<'a>>) -> Option<Construct<'a>> {
-        let if_regex = Regex::new(r"^if (.+) \{$").unwrap();
-        let while_regex = Regex::new(r"^while (.+) \{$").unwrap();
-        let for_regex = Regex::new(r"^for ([a-z_]+) (.*) \{$").unwrap();
-
-        // form `if EXPRESSION {`
-        if let Some(capture) = if_regex.captures(&construct) {
-            let expression = capture.get(1).unwrap().as_str();
-            let expression = Expression::parse(expression, user_fns).unwrap();
-            let sub_lines = get_sub_program(lines);
-            let sub = Program::from_lines(&mut sub_lines.iter(), user_fns);
-            Some(Construct::If(expression, sub))
-        } 
-        // form `while EXPRESSION {`
-        else if let Some(capture) = while_regex.captures(&construct) {
-            let expression = capture.get(1).unwrap().as_str();
-            let expression = Expression::parse(expression, user_fns).unwrap();
-            let sub_lines = get_sub_program(lines);
-            let sub = Program::from_lines(&mut sub_lines.iter(), user_fns);
-            Some(Construct::While(expression, sub))
-        } 
-        // form `for VAR_NAME EXPRESSION EXPRESSION {`
-        else if let Some(capture) = for_regex.captures(&construct) {
-            let iterating = capture.get(1).unwrap().as_str();
-            let args = capture.get(2).unwrap().as_str();
-            let mut args = Expression::evaluate_arguments(args, user_fns);
-            match args.len() {
-                2 => {
-                    let start = args.remove(0);
-                    let end = args.remove(0);
-                    let sub_lines = get_sub_program(lines);
-                    let subprogram = Program::from_lines(&mut sub_lines.iter(), user_fns);
-                    Some(Construct::For(iterating, start, end, subprogram))
-                }
-                _ => panic!("invalid for loop \"{}\"", construct),
-            }
-        } else {
-            None
-        }
-    }
+#[derive(Debug)]
+pub enum Pattern<'a> {
+    // matches values equal to it
+    Literal(Value<'a>),
+    // matches values from the first up to but not including the second
+    Range(Value<'a>, Value<'a>),
+    // a struct or enum variant by name, along with the variables its fields are given to
+    Named(&'a str, Vec<Variable<'a>>, Span),
+    // a named pattern once the type it names has been found
+    Type(Rc<StructType<'a>>, Vec<Variable<'a>>),
+}
 
-    // do what the if/while/for does
-    pub fn apply(&self, data_store: &mut DataStore<'a>) {
+impl<'a> Construct<'a> {
+    // do what the if/while/for does. loops deal with any break or continue from their program, anything
+    // else is passed back out
+    pub fn apply(&self, data_store: &mut DataStore<'a>) -> Result<Flow<'a>, MyLangError> {
         match self {
-            Construct::If(expr, sub) => {
-                if Expression::evaluate(expr, data_store).unwrap() != 0 {
-                    sub.run_with(data_store);
+            Construct::If(arms, otherwise, _) => {
+                for (expr, sub) in arms {
+                    if Expression::evaluate(expr, data_store)?.truthy("if").map_err(|e| e.at(expr.span()))? {
+                        return sub.run_with(data_store);
+                    }
+                }
+                if let Some(sub) = otherwise {
+                    return sub.run_with(data_store);
                 }
             }
-            Construct::While(expr, sub) => {
-                while Expression::evaluate(expr, data_store).unwrap() != 0 {
-                    sub.run_with(data_store);
+            Construct::While(expr, sub, _) => {
+                while Expression::evaluate(expr, data_store)?.truthy("while").map_err(|e| e.at(expr.span()))? {
+                    match sub.run_with(data_store)? {
+                        Flow::Break => break,
+                        Flow::Return(val) => return Ok(Flow::Return(val)),
+                        Flow::Next | Flow::Continue => (),
+                    }
                 }
             }
-            // for loop may have a newly declared loop var, so mak data store note that it may 
-            // be able to be deleted. run the loop, then remove it if not declared prior to this loop
-            Construct::For(var, start, end, sub) => {
-                data_store.expand();
-                let start = Expression::evaluate(start, data_store).unwrap();
-                let end = Expression::evaluate(end, data_store).unwrap();
-                for i in start..end {
-                    data_store.put(*var, i);
-                    sub.run_with(data_store);
+            Construct::For(var, start, end, sub, _) => return Construct::run_for(*var, start, end, sub, data_store),
+            Construct::ForEach(var, list, sub, _) => return Construct::run_for_each(*var, list, sub, data_store),
+            Construct::Match(expr, arms, otherwise, span) => {
+                let val = Expression::evaluate(expr, data_store)?;
+                for (pattern, sub) in arms {
+                    if pattern.matches(&val, data_store)? {
+                        return sub.run_with(data_store);
+                    }
+                }
+                match otherwise {
+                    Some(sub) => return sub.run_with(data_store),
+                    None => return Err(ErrorKind::NoMatch(val.to_string()).at(*span)),
                 }
-                data_store.contract();
             }
         }
+        Ok(Flow::Next)
     }
 
-    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>) -> Construct<'a> {
+    fn run_for(var: Variable<'a>, start: &Expression<'a>, end: &Expression<'a>, sub: &Program<'a>, data_store: &mut DataStore<'a>) -> Result<Flow<'a>, MyLangError> {
+        let start = Expression::evaluate(start, data_store)?.as_int("for").map_err(|e| e.at(start.span()))?;
+        let end = Expression::evaluate(end, data_store)?.as_int("for").map_err(|e| e.at(end.span()))?;
+        for i in start..end {
+            data_store.put(var, Value::Int(i))?;
+            match sub.run_with(data_store)? {
+                Flow::Break => break,
+                Flow::Return(val) => return Ok(Flow::Return(val)),
+                Flow::Next | Flow::Continue => (),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn run_for_each(var: Variable<'a>, list: &Expression<'a>, sub: &Program<'a>, data_store: &mut DataStore<'a>) -> Result<Flow<'a>, MyLangError> {
+        let items = match Expression::evaluate(list, data_store)? {
+            Value::Map(map) => Value::list(sorted_keys(&map)),
+            items => items,
+        };
+        let items = items.as_list("for").map_err(|e| e.at(list.span()))?;
+        let mut i = 0;
+        loop {
+            // the list mustn't still be borrowed while the loop body runs, as the body may change it
+            let item = match items.borrow().get(i) {
+                Some(item) => item.clone(),
+                None => break,
+            };
+            data_store.put(var, item)?;
+            match sub.run_with(data_store)? {
+                Flow::Break => break,
+                Flow::Return(val) => return Ok(Flow::Return(val)),
+                Flow::Next | Flow::Continue => (),
+            }
+            i += 1;
+        }
+        Ok(Flow::Next)
+    }
+
+    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &mut Scope<'a>) -> Result<Construct<'a>, MyLangError> {
+        let construct = match self {
+            Construct::If(arms, otherwise, span) => {
+                let arms = arms.iter()
+                    .map(|(exp, prog)| Ok((exp.optimise(user_fns, scope)?, prog.optimise(user_fns, scope)?)))
+                    .collect::<Result<_, MyLangError>>()?;
+                let otherwise = match otherwise {
+                    Some(prog) => Some(prog.optimise(user_fns, scope)?),
+                    None => None,
+                };
+                Construct::If(arms, otherwise, *span)
+            }
+            Construct::While(exp, prog, span) => Construct::While(exp.optimise(user_fns, scope)?, prog.optimise(user_fns, scope)?, *span),
+            // the loop variable can only be used inside the loop, unless it was already a variable before it
+            Construct::For(var, start, end, prog, span) => {
+                let start = start.optimise(user_fns, scope)?;
+                let end = end.optimise(user_fns, scope)?;
+                scope.expand();
+                let var = scope.declare(*var);
+                let prog = prog.optimise(user_fns, scope);
+                scope.contract();
+                Construct::For(var, start, end, prog?, *span)
+            }
+            Construct::ForEach(var, list, prog, span) => {
+                let list = list.optimise(user_fns, scope)?;
+                scope.expand();
+                let var = scope.declare(*var);
+                let prog = prog.optimise(user_fns, scope);
+                scope.contract();
+                Construct::ForEach(var, list, prog?, *span)
+            }
+            // the variables a pattern binds are new ones, which can only be used in its arm
+            Construct::Match(exp, arms, otherwise, span) => {
+                let exp = exp.optimise(user_fns, scope)?;
+                let arms = arms.iter()
+                    .map(|(pattern, prog)| {
+                        scope.expand();
+                        let arm = pattern.optimise(user_fns, scope)
+                            .and_then(|pattern| Ok((pattern, prog.optimise(user_fns, scope)?)));
+                        scope.contract();
+                        arm
+                    })
+                    .collect::<Result<_, MyLangError>>()?;
+                let otherwise = match otherwise {
+                    Some(prog) => Some(prog.optimise(user_fns, scope)?),
+                    None => None,
+                };
+                Construct::Match(exp, arms, otherwise, *span)
+            }
+        };
+        Ok(construct)
+    }
+}
+
+impl<'a> Pattern<'a> {
+    // whether the value matches, giving a struct's fields to the pattern's variables if it does
+    fn matches(&self, val: &Value<'a>, data_store: &mut DataStore<'a>) -> Result<bool, MyLangError> {
         match self {
-            Construct::If(exp, prog) => Construct::If(exp.optimise(user_fns), prog.optimise(user_fns)),
-            Construct::While(exp, prog) => Construct::While(exp.optimise(user_fns), prog.optimise(user_fns)),
-            Construct::For(var, start, end, prog) => Construct::For(var, start.optimise(user_fns), end.optimise(user_fns), prog.optimise(user_fns))
+            Pattern::Literal(literal) => Ok(val == literal),
+            Pattern::Range(low, high) => {
+                let above = matches!(val.compare(low), Some(Ordering::Equal | Ordering::Greater));
+                Ok(above && val.compare(high) == Some(Ordering::Less))
+            }
+            Pattern::Named(name, _, span) => Err(ErrorKind::UndefinedType(String::from(*name)).at(*span)),
+            Pattern::Type(kind, vars) => match val {
+                Value::Struct(record) if Rc::ptr_eq(&record.kind, kind) => {
+                    let fields = record.fields.borrow().clone();
+                    for (var, field) in vars.iter().zip(fields) {
+                        data_store.put(*var, field)?;
+                    }
+                    Ok(true)
+                }
+                _ => Ok(false),
+            },
         }
     }
+
+    // a named pattern must name a struct or enum variant, and have a variable for each of its fields
+    fn optimise(&self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &mut Scope<'a>) -> Result<Pattern<'a>, MyLangError> {
+        let pattern = match self {
+            Pattern::Literal(val) => Pattern::Literal(val.clone()),
+            Pattern::Range(low, high) => Pattern::Range(low.clone(), high.clone()),
+            Pattern::Named(name, vars, span) => {
+                let kind = user_fns.get(name)
+                    .and_then(|func| func.kind.as_ref())
+                    .ok_or_else(|| ErrorKind::UndefinedType(String::from(*name)).at(*span))?;
+                if vars.len() != kind.fields.len() {
+                    let error = ErrorKind::Arity { name: String::from(*name), expected: kind.fields.len(), found: vars.len() };
+                    return Err(error.at(*span));
+                }
+                Pattern::Type(Rc::clone(kind), vars.iter().map(|var| scope.declare_new(*var)).collect())
+            }
+            Pattern::Type(kind, vars) => Pattern::Type(Rc::clone(kind), vars.clone()),
+        };
+        Ok(pattern)
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::program::tests::run;
+    use crate::{ErrorKind, Span};
+
+    // which arm of an if with an elif and an else runs for `n`, along with the conditions that were checked
+    fn arm_for(n: i64) -> Vec<String> {
+        run(&format!("
+            arm: \"\"
+            checked: []
+            func check c {{
+                push checked c
+                res: == c n
+            }}
+            n: {}
+            if check 1 {{
+                arm: \"if\"
+            }} elif check 2 {{
+                arm: \"first elif\"
+            }} elif check 2 {{
+                arm: \"second elif\"
+            }} else {{
+                arm: \"else\"
+            }}
+        ", n)).unwrap()[..2].to_vec()
+    }
+
+    #[test]
+    fn only_the_first_arm_that_holds_runs() {
+        assert_eq!(arm_for(1), ["if", "[1]"]);
+        assert_eq!(arm_for(2), ["first elif", "[1 2]"]);
+        assert_eq!(run("x: 0\nif false {\n    x: 1\n} elif false {\n    x: 2\n}").unwrap(), ["0"]);
+    }
+
+    #[test]
+    fn else_runs_when_nothing_else_holds() {
+        assert_eq!(arm_for(3), ["else", "[1 2 2]"]);
+    }
+
+    #[test]
+    fn elif_and_else_must_follow_an_if() {
+        let error = run("if true { x: 1 } else { x: 2 } elif true { x: 3 }").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::Expected { expected: String::from("an expression"), found: String::from("\"elif\"") });
+        assert_eq!(error.span(), Some(Span { file: 0, line: 1, column: 31, len: 4 }));
+        assert!(run("else { x: 1 }").is_err());
+        // `} else {` has to be on the same line as the `}` it follows
+        assert!(run("if true { x: 1 }\nelse { x: 2 }").is_err());
+        assert!(run("if true { x: 1 } elif { x: 2 }").is_err());
+    }
+
+    #[test]
+    fn each_condition_must_be_true_or_false() {
+        let error = run("if false { x: 1 } elif \"a\" { x: 2 }").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::Type { name: String::from("if"), found: String::from("str") });
+        assert_eq!(error.span(), Some(Span { file: 0, line: 1, column: 23, len: 3 }));
+    }
+
+    const SHAPES: &str = "
+        enum shape {
+            circle r
+            rect w h
+            empty
+        }
+        struct point x y
+    ";
+
+    // what the arm of `arms` matching each of `values` gives back
+    fn matched(arms: &str, values: &str) -> Result<String, ErrorKind> {
+        let text = format!("{}func f v {{\n    match v {{\n{}\n    }}\n}}\nout: map @f [{}]", SHAPES, arms, values);
+        run(&text).map(|globals| globals[0].clone()).map_err(|e| e.kind().clone())
+    }
+
+    #[test]
+    fn a_match_picks_the_arm_for_the_variant_and_names_its_fields() {
+        let arms = "circle r { return * 3 r }\nrect w h { return - w h }\nempty { return 0 }\npoint a b { return + a b }";
+        assert_eq!(matched(arms, "(circle 2) (rect 5 3) empty (point 1 2)"), Ok(String::from("[6 2 0 3]")));
+    }
+
+    #[test]
+    fn a_match_can_pick_an_arm_by_value_or_range() {
+        let arms = "0 { return \"none\" }\n1..4 { return \"few\" }\n\"many\" { return \"lots\" }\nelse { return \"more\" }";
+        assert_eq!(matched(arms, "0 1 3 4 \"many\" (circle 1)"), Ok(String::from("[\"none\" \"few\" \"few\" \"more\" \"lots\" \"more\"]")));
+    }
+
+    #[test]
+    fn a_value_no_arm_matches_is_an_error() {
+        assert_eq!(matched("circle r { return r }", "(circle 1) empty"), Err(ErrorKind::NoMatch(String::from("empty"))));
+        let error = run("x: 5\nmatch x {\n    1 { y: 1 }\n}").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::NoMatch(String::from("5")));
+        assert_eq!(error.span(), Some(Span { file: 0, line: 2, column: 0, len: 7 }));
+    }
+
+    #[test]
+    fn a_pattern_must_name_a_type_and_each_of_its_fields() {
+        assert_eq!(matched("square s { return s }", "1"), Err(ErrorKind::UndefinedType(String::from("square"))));
+        assert_eq!(matched("rect w { return w }", "1"), Err(ErrorKind::Arity { name: String::from("rect"), expected: 2, found: 1 }));
+        let error = run("match 1 {\n    else { x: 1 }\n    2 { x: 2 }\n}").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::Expected { expected: String::from("\"}\" after the else arm"), found: String::from("\"2\"") });
+        assert!(run("match 1 {\n}").is_err());
+    }
+}
@@ -1,50 +1,175 @@
-// simulates a stack by making 'layers' using a vec. when a layer is removed, its variables are too.
-// if a new var is added, it is added to the top level so the program scopes variables appropriately
+use std::rc::Rc;
+
+use crate::{ErrorKind, MyLangError, Overflow, Slot, Value, Variable};
+
+// how many user function calls can be in progress at once, unless the host allows more with
+// `DataStore::with_call_depth`. a call uses anything from a couple of KB of stack in a release build to tens
+// of KB in a debug one, so a program often runs out of the stack it's allowed before it makes this many,
+// which is an error too. a host that wants deep recursion should run the program on a thread it starts
+// with a bigger stack, and pass how much of it the program can use to `DataStore::with_stack_size`
+pub const MAX_CALL_DEPTH: usize = 1_000;
+
+// how much of the stack of the thread running a program it can use, counted from where its data store is
+// made, unless the host says otherwise with `DataStore::with_stack_size`. it leaves room to spare on the
+// 2MB threads rust starts by default and the 8MB main thread most platforms have
+pub const STACK_SIZE: usize = 1024 * 1024;
+
+// a stack of frames, one for the top level program and one for each user function call in progress.
+// variables are found by their slot in the current frame, which is worked out when the program is
+// optimised. a frame grows as new variables are assigned, and shrinks back when the block they were
+// made in ends. slots past the end of the variables that are in scope may still hold stale values, but
+// the program can never refer to them. the main program's top level variables are kept apart from the
+// frames so functions can read them too
 pub struct DataStore<'a> {
-    vars: Vec<&'a str>,
-    vals: Vec<i64>,
-    levels: Vec<usize>,
+    vals: Vec<Value<'a>>,
+    globals: Vec<Value<'a>>,
+    // where the current frame starts in `vals`
+    base: usize,
+    // how many frames there are on top of the top level program's
+    depth: usize,
+    args: Rc<[String]>,
+    overflow: Overflow,
+    call_depth: usize,
+    // the lowest address the stack can grow down to before a call or built in gives up with an error,
+    // rather than letting the thread overflow its stack
+    stack_limit: usize,
 }
 
-impl <'a> DataStore<'a> {
+impl Default for DataStore<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> DataStore<'a> {
     pub fn new() -> DataStore<'a> {
+        DataStore::with_args(Vec::new())
+    }
+
+    // the script arguments, which can be read from every frame
+    pub fn with_args(args: Vec<String>) -> DataStore<'a> {
         DataStore {
-            vars: Vec::new(),
             vals: Vec::new(),
-            levels: Vec::new(),
+            globals: Vec::new(),
+            base: 0,
+            depth: 0,
+            args: Rc::from(args),
+            overflow: Overflow::default(),
+            call_depth: MAX_CALL_DEPTH,
+            stack_limit: stack_position().saturating_sub(STACK_SIZE),
         }
     }
 
-    pub fn expand(&mut self) {
-        self.levels.push(0);
+    // what int arithmetic does when its result doesn't fit in an int
+    pub fn with_overflow(self, overflow: Overflow) -> DataStore<'a> {
+        DataStore { overflow, ..self }
+    }
+
+    // how many user function calls can be in progress at once. running out of stack first is an error too
+    pub fn with_call_depth(self, call_depth: usize) -> DataStore<'a> {
+        DataStore { call_depth, ..self }
+    }
+
+    // how much stack the program can use from here on. the thread should have some to spare beyond it for
+    // the built in that finds it has run out to finish what it's doing
+    pub fn with_stack_size(self, stack_size: usize) -> DataStore<'a> {
+        DataStore { stack_limit: stack_position().saturating_sub(stack_size), ..self }
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    // forget the variables from `slot` onwards at the end of the block they were made in
+    #[inline]
+    pub fn clear_from(&mut self, slot: usize) {
+        self.vals.truncate(self.base + slot);
+    }
+
+    // where the next frame will start. a function call pushes its argument values from here while the
+    // caller's frame is still current, as that's where they're worked out, then calls `enter` with it
+    pub fn top(&self) -> usize {
+        self.vals.len()
+    }
+
+    #[inline]
+    pub fn push(&mut self, val: Value<'a>) {
+        self.vals.push(val);
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_depth
     }
 
-    pub fn contract(&mut self) {
-        for _ in 0..*self.levels.last().unwrap() {
-            self.vals.pop();
-            self.vars.pop();
+    // whether the program has used up the stack it was given, which happens when it calls functions or works
+    // out built ins inside each other too deeply
+    #[inline]
+    pub fn check_stack(&self) -> Result<(), MyLangError> {
+        if stack_position() < self.stack_limit {
+            return Err(ErrorKind::StackOverflow.into());
         }
-        self.levels.pop();
+        Ok(())
     }
 
-    pub fn put(&mut self, var: &'a str, val: i64) {
-        if let Some((i, _)) = self.vars.iter().enumerate().find(|(_, &v)| v == var) {
-            self.vals[i] = val;
+    // make the frame starting at `base` the current one, giving back the caller's frame to `leave` with
+    pub fn enter(&mut self, base: usize) -> usize {
+        self.depth += 1;
+        std::mem::replace(&mut self.base, base)
+    }
+
+    // drop the current frame and go back to the caller's
+    pub fn leave(&mut self, caller: usize) {
+        self.vals.truncate(self.base);
+        self.base = caller;
+        self.depth -= 1;
+    }
+
+    // a variable is always given the next free slot when it's first assigned, so it's either already
+    // in the frame, or replacing a stale value, or goes on the end of it. globals are assigned in the
+    // order of their slots as the main program's top level runs from top to bottom
+    #[inline]
+    pub fn put(&mut self, var: Variable, val: Value<'a>) -> Result<(), MyLangError> {
+        let (vals, i) = match slot(var)? {
+            Slot::Local(slot) => (&mut self.vals, self.base + slot),
+            Slot::Global(slot) => (&mut self.globals, slot),
+        };
+        if i < vals.len() {
+            vals[i] = val;
         }
         else {
-            let last_ind = self.levels.len() - 1;
-            self.levels[last_ind] += 1;
-            self.vars.push(var);
-            self.vals.push(val);
+            vals.push(val);
         }
+        Ok(())
     }
 
-    pub fn get(&mut self, var: &'a str) -> Option<i64> {
-        for (i, v) in self.vars.iter().enumerate() {
-            if *v == var {
-                return Some(self.vals[i]);
-            }
-        }
-        None
+    // a function can be called before a global it reads has been assigned
+    #[inline]
+    pub fn get(&self, var: Variable) -> Result<Value<'a>, MyLangError> {
+        let val = match slot(var)? {
+            Slot::Local(slot) => self.vals.get(self.base + slot),
+            Slot::Global(slot) => self.globals.get(slot),
+        };
+        val.cloned().ok_or_else(|| ErrorKind::UndefinedVariable(String::from(var.name)).into())
     }
 }
+
+// roughly where the top of the stack is. it grows down towards lower addresses on every platform this runs on
+#[inline(always)]
+fn stack_position() -> usize {
+    let here = 0u8;
+    &here as *const u8 as usize
+}
+
+// variables only have a slot once the program has been optimised
+#[inline]
+fn slot(var: Variable) -> Result<Slot, MyLangError> {
+    var.slot.ok_or_else(|| ErrorKind::UndefinedVariable(String::from(var.name)).into())
+}
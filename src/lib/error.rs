@@ -0,0 +1,374 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::{Sources, Span};
+
+// an error along with where it happened, if that's known yet. it's kept behind a box so the results every
+// expression gives back stay small, as they're passed around far more often than anything goes wrong
+#[derive(Debug, Clone, PartialEq)]
+pub struct MyLangError(Box<Located>);
+
+#[derive(Debug, Clone, PartialEq)]
+struct Located {
+    kind: ErrorKind,
+    span: Option<Span>,
+    // the user function calls the error passed back out through, innermost first
+    trace: Vec<Span>,
+}
+
+// everything that can go wrong while parsing or running a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    Parse(String),
+    Expected { expected: String, found: String },
+    UnclosedBlock,
+    UnclosedString,
+    UnclosedComment,
+    OutsideLoop(String),
+    OutsideFunction(String),
+    NotTopLevel(String),
+    Import { path: String, reason: String },
+    // the files that import each other, starting and ending with the same one
+    ImportCycle(Vec<String>),
+    Arity { name: String, expected: usize, found: usize },
+    TooFewArguments { name: String, expected: usize, found: usize },
+    UndefinedVariable(String),
+    UndefinedFunction(String),
+    // a function, struct or enum variant declared with the same name as one declared at `first`
+    DuplicateFunction { name: String, first: Span },
+    // a function, variable or argument given the name of a built in, which would always be used in its place
+    BuiltInName(String),
+    UndefinedType(String),
+    NoValue(String),
+    // a call that gives no value used as the left hand side of an infix operator
+    NoValueOperand { name: String, operator: String },
+    // a negative number written right after a variable, which calls the function it holds rather than
+    // taking the number away
+    NegativeArgument(String),
+    // `a == b == c`, which would compare whether `a == b` is the same as `c`
+    ChainedEquality(String),
+    InvalidArgument(String),
+    // a built in that only works on ints small enough to fit in 64 bits was given a bigger one
+    BigIntUnsupported(String),
+    Type { name: String, found: String },
+    IndexOutOfRange { index: i64, len: usize },
+    MissingKey(String),
+    MissingField { name: String, field: String },
+    NoMatch(String),
+    DivisionByZero,
+    Overflow,
+    RecursionLimit(usize),
+    StackOverflow,
+    // brackets, lists, indexes or blocks nested inside each other more deeply than the parser allows
+    NestingLimit(usize),
+}
+
+impl From<ErrorKind> for MyLangError {
+    fn from(kind: ErrorKind) -> Self {
+        MyLangError(Box::new(Located { kind, span: None, trace: Vec::new() }))
+    }
+}
+
+impl ErrorKind {
+    // the error, noted as happening at `span`
+    pub fn at(self, span: Span) -> MyLangError {
+        MyLangError::from(self).at(span)
+    }
+}
+
+impl MyLangError {
+    // note where an error happened, unless something closer to the cause already has
+    pub fn at(mut self, span: Span) -> MyLangError {
+        self.0.span.get_or_insert(span);
+        self
+    }
+
+    // note that an error came out of the user function called at `span`
+    pub fn called_from(mut self, span: Span) -> MyLangError {
+        match self.0.span {
+            Some(_) => self.0.trace.push(span),
+            None => self.0.span = Some(span),
+        }
+        self
+    }
+
+    // the error without any location information
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.0.span
+    }
+
+    // describe the error rustc style, quoting the line it happened on
+    pub fn render(&self, sources: &Sources) -> String {
+        let mut out = format!("error: {}\n", self.kind());
+        if let Some(span) = &self.0.span {
+            let trace = &self.0.trace;
+            let source = sources.get(span.file);
+            let line = source.text.lines().nth(span.line as usize - 1).unwrap_or("");
+            let gutter = " ".repeat(span.line.to_string().len());
+            let (column, width) = display_columns(line, *span);
+
+            let _ = writeln!(out, "{}--> {}:{}:{}", gutter, source.name, span.line, column + 1);
+            let _ = writeln!(out, "{} |", gutter);
+            let _ = writeln!(out, "{} | {}", span.line, line);
+            let _ = writeln!(out, "{} | {}{}", gutter, " ".repeat(column), "^".repeat(width));
+            let notes = trace_notes(sources, trace);
+            if notes.len() > MAX_TRACE_NOTES {
+                let (start, end) = (&notes[..MAX_TRACE_NOTES / 2], &notes[notes.len() - MAX_TRACE_NOTES / 2..]);
+                let skipped = notes.len() - start.len() - end.len();
+                for note in start {
+                    let _ = writeln!(out, "{} = note: {}", gutter, note);
+                }
+                let _ = writeln!(out, "{} = note: ... {} more", gutter, skipped);
+                for note in end {
+                    let _ = writeln!(out, "{} = note: {}", gutter, note);
+                }
+            } else {
+                for note in &notes {
+                    let _ = writeln!(out, "{} = note: {}", gutter, note);
+                }
+            }
+            if let ErrorKind::DuplicateFunction { first, .. } = self.kind() {
+                let source = sources.get(first.file);
+                let line = source.text.lines().nth(first.line as usize - 1).unwrap_or("");
+                let (column, _) = display_columns(line, *first);
+                let _ = writeln!(out, "{} = note: first declared at {}:{}:{}", gutter, source.name, first.line, column + 1);
+            }
+        }
+        out
+    }
+}
+
+// how many notes about the calls an error came out of are shown before the ones in the middle are left out
+const MAX_TRACE_NOTES: usize = 20;
+
+// the longest run of calls that's looked for repeating, like functions calling each other in a circle
+const MAX_CYCLE: usize = 16;
+
+// a note for each call an error came out of, innermost first. recursive functions call each other from the
+// same places over and over, so a run of calls that repeats is only shown once along with how often it did
+fn trace_notes(sources: &Sources, trace: &[Span]) -> Vec<String> {
+    let mut notes = Vec::new();
+    let mut i = 0;
+    while i < trace.len() {
+        let (cycle, repeats) = repeating(&trace[i..]);
+        for &call in &trace[i..i + cycle] {
+            let source = sources.get(call.file);
+            let line = source.text.lines().nth(call.line as usize - 1).unwrap_or("");
+            let (column, _) = display_columns(line, call);
+            notes.push(format!("called from {}:{}:{}", source.name, call.line, column + 1));
+        }
+        if repeats > 1 && cycle == 1 {
+            let _ = write!(notes.last_mut().unwrap(), " ({} times)", repeats);
+        } else if repeats > 1 {
+            notes.push(format!("the {} calls above repeat {} times", cycle, repeats));
+        }
+        i += cycle * repeats;
+    }
+    notes
+}
+
+// the length of the run of calls at the start of `calls` that repeats over the most of them, and how many
+// times it does
+fn repeating(calls: &[Span]) -> (usize, usize) {
+    let mut best = (1, 1);
+    for cycle in 1..=MAX_CYCLE.min(calls.len() / 2) {
+        let repeats = calls.chunks_exact(cycle)
+            .take_while(|chunk| *chunk == &calls[..cycle])
+            .count();
+        if repeats > 1 && cycle * repeats > best.0 * best.1 {
+            best = (cycle, repeats);
+        }
+    }
+    best
+}
+
+// where a span starts on its line and how wide it is, counted in characters rather than bytes
+fn display_columns(line: &str, span: Span) -> (usize, usize) {
+    let start = span.column as usize;
+    let column = line.get(..start).map_or(0, |before| before.chars().count());
+    let width = line.get(start..start + span.len as usize)
+        .map_or(1, |part| part.chars().count().max(1));
+    (column, width)
+}
+
+impl fmt::Display for MyLangError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.span {
+            Some(span) => write!(f, "{} (line {}, column {})", self.0.kind, span.line, span.column + 1),
+            None => write!(f, "{}", self.0.kind),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Parse(input) => write!(f, "unexpected input : \"{}\"", input),
+            ErrorKind::Expected { expected, found } => write!(f, "expected {} but found {}", expected, found),
+            ErrorKind::UnclosedBlock => write!(f, "unclosed pair of squiggly brackets"),
+            ErrorKind::UnclosedString => write!(f, "string is missing its closing quote"),
+            ErrorKind::UnclosedComment => write!(f, "comment is missing its closing \"*/\""),
+            ErrorKind::OutsideLoop(keyword) => write!(f, "\"{}\" used outside of a loop", keyword),
+            ErrorKind::OutsideFunction(keyword) => write!(f, "\"{}\" used outside of a function", keyword),
+            ErrorKind::NotTopLevel(keyword) => write!(f, "\"{}\" used inside a block", keyword),
+            ErrorKind::Import { path, reason } => write!(f, "could not import \"{}\" : {}", path, reason),
+            ErrorKind::ImportCycle(files) => write!(f, "import cycle : {}", files.join(" -> ")),
+            ErrorKind::Arity { name, expected, found } => {
+                write!(f, "\"{}\" takes {} argument(s) but was given {}", name, expected, found)
+            }
+            ErrorKind::TooFewArguments { name, expected, found } => {
+                write!(f, "\"{}\" takes at least {} argument(s) but was given {}", name, expected, found)
+            }
+            ErrorKind::UndefinedVariable(name) => write!(f, "undefined variable \"{}\"", name),
+            ErrorKind::UndefinedFunction(name) => write!(f, "undefined function \"{}\"", name),
+            ErrorKind::DuplicateFunction { name, .. } => write!(f, "\"{}\" is already declared", name),
+            ErrorKind::BuiltInName(name) => write!(f, "\"{}\" is the name of a built in and can't be declared", name),
+            ErrorKind::UndefinedType(name) => write!(f, "undefined struct or variant \"{}\"", name),
+            ErrorKind::NoValue(name) => write!(f, "\"{}\" does not produce a value", name),
+            ErrorKind::NoValueOperand { name, operator } => write!(
+                f,
+                "\"{}\" does not produce a value for \"{}\" to use. to pass it the result, put the operation in brackets, like \"{} (a {} b)\"",
+                name, operator, name, operator,
+            ),
+            ErrorKind::NegativeArgument(number) => write!(
+                f,
+                "{} would be passed as an argument to the function value before it. to take it away, put a space after the \"-\", like \"n - {}\", or to pass it, put it in brackets, like \"n ({})\"",
+                number, &number[1..], number,
+            ),
+            ErrorKind::ChainedEquality(operator) => write!(
+                f,
+                "\"{}\" can't be chained, as \"a {} b {} c\" would compare the result of the first with c. to compare them all, write \"{} a b c\"",
+                operator, operator, operator, operator,
+            ),
+            ErrorKind::InvalidArgument(reason) => write!(f, "invalid argument : {}", reason),
+            ErrorKind::BigIntUnsupported(name) => write!(f, "\"{}\" does not support big ints", name),
+            ErrorKind::Type { name, found } => write!(f, "\"{}\" can't be used with {}", name, found),
+            ErrorKind::IndexOutOfRange { index, len } => {
+                write!(f, "index {} is out of range for a list of length {}", index, len)
+            }
+            ErrorKind::MissingKey(key) => write!(f, "map has no key {}", key),
+            ErrorKind::MissingField { name, field } => write!(f, "{} has no field \"{}\"", name, field),
+            ErrorKind::NoMatch(val) => write!(f, "no pattern matches {}", val),
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::Overflow => write!(f, "integer overflow"),
+            ErrorKind::RecursionLimit(limit) => write!(f, "more than {} function calls in progress at once", limit),
+            ErrorKind::StackOverflow => write!(f, "ran out of stack, function calls or expressions are nested too deeply"),
+            ErrorKind::NestingLimit(limit) => write!(f, "brackets or blocks nested more than {} deep", limit),
+        }
+    }
+}
+
+impl Error for MyLangError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Overflow;
+    use crate::program::tests::run_with_overflow;
+
+    fn call(line: u32) -> Span {
+        Span { file: 0, line, column: 4, len: 3 }
+    }
+
+    // the notes rendered about the calls `trace` lists, innermost first
+    fn notes(trace: &[Span]) -> Vec<String> {
+        let mut sources = Sources::new();
+        let text = (1..=40).map(|i| format!("    f{}\n", i)).collect();
+        sources.add(String::from("a.jcw"), text);
+        let error = trace.iter().fold(ErrorKind::Overflow.at(call(1)), |error, &span| error.called_from(span));
+        error.render(&sources)
+            .lines()
+            .filter_map(|line| line.trim_start().strip_prefix("= note: ").map(String::from))
+            .collect()
+    }
+
+    // how the error `text` can't be parsed or run for is shown when there are no sources to quote
+    fn failure(text: &str) -> String {
+        run_with_overflow(text, Overflow::Error).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn bad_programs_give_back_an_error_saying_where_they_went_wrong() {
+        assert_eq!(failure("x: + 1"), "\"+\" takes at least 2 argument(s) but was given 1 (line 1, column 4)");
+        assert_eq!(failure("x: % 1"), "\"%\" takes 2 argument(s) but was given 1 (line 1, column 4)");
+        assert_eq!(failure("x: 1\nif true {"), "unclosed pair of squiggly brackets (line 2, column 1)");
+        assert_eq!(failure("x: (1"), "expected \")\" but found end of file (line 1, column 6)");
+        assert_eq!(failure("x: 1 2"), "expected end of line but found \"2\" (line 1, column 6)");
+        assert_eq!(failure("func f a {\n}\nx: f 1 2"), "\"f\" takes 1 argument(s) but was given 2 (line 3, column 4)");
+        assert_eq!(failure("x: y"), "undefined variable \"y\" (line 1, column 4)");
+        assert_eq!(failure("x: call 1"), "\"call\" can't be used with int (line 1, column 4)");
+        assert_eq!(failure("x: 1\nx: / x 0"), "division by zero (line 2, column 4)");
+        assert_eq!(failure("x: * 9223372036854775807 2"), "integer overflow (line 1, column 4)");
+        assert_eq!(failure("x: [1 2][5]"), "index 5 is out of range for a list of length 2 (line 1, column 4)");
+    }
+
+    #[test]
+    fn an_error_is_rendered_with_the_line_it_happened_on() {
+        let mut sources = Sources::new();
+        sources.add(String::from("a.jcw"), String::from("x: 1\ny: + x \"a\"\n"));
+        let error = ErrorKind::Type { name: String::from("+"), found: String::from("int and str") };
+        assert_eq!(error.at(Span { file: 0, line: 2, column: 3, len: 7 }).render(&sources), "\
+error: \"+\" can't be used with int and str
+ --> a.jcw:2:4
+  |
+2 | y: + x \"a\"
+  |    ^^^^^^^
+");
+        assert_eq!(MyLangError::from(ErrorKind::DivisionByZero).render(&sources), "error: division by zero\n");
+    }
+
+    #[test]
+    fn an_error_is_a_single_pointer() {
+        assert_eq!(std::mem::size_of::<MyLangError>(), std::mem::size_of::<usize>());
+    }
+
+    #[test]
+    fn an_error_keeps_the_closest_location_to_its_cause() {
+        let error = MyLangError::from(ErrorKind::DivisionByZero);
+        assert_eq!(error.span(), None);
+        let error = error.at(call(1)).at(call(2));
+        assert_eq!(error.span(), Some(call(1)));
+        assert_eq!(error.kind(), &ErrorKind::DivisionByZero);
+
+        // an error with no location yet takes that of the call it came out of
+        let error = MyLangError::from(ErrorKind::Overflow).called_from(call(3)).called_from(call(4));
+        assert_eq!(error.span(), Some(call(3)));
+        assert_eq!(error, ErrorKind::Overflow.at(call(3)).called_from(call(4)));
+    }
+
+    #[test]
+    fn recursion_from_one_place_is_shown_once() {
+        assert_eq!(notes(&[call(2), call(2), call(2), call(3)]), [
+            "called from a.jcw:2:5 (3 times)",
+            "called from a.jcw:3:5",
+        ]);
+    }
+
+    #[test]
+    fn functions_calling_each_other_are_shown_once() {
+        let mut trace = [call(2), call(3), call(4)].repeat(1000);
+        trace.push(call(5));
+        assert_eq!(notes(&trace), [
+            "called from a.jcw:2:5",
+            "called from a.jcw:3:5",
+            "called from a.jcw:4:5",
+            "the 3 calls above repeat 1000 times",
+            "called from a.jcw:5:5",
+        ]);
+    }
+
+    #[test]
+    fn a_long_trace_leaves_out_the_middle() {
+        let trace = (2..=40).map(call).collect::<Vec<_>>();
+        let notes = notes(&trace);
+        assert_eq!(notes.len(), MAX_TRACE_NOTES + 1);
+        assert_eq!(notes[0], "called from a.jcw:2:5");
+        assert_eq!(notes[MAX_TRACE_NOTES / 2], "... 19 more");
+        assert_eq!(notes[MAX_TRACE_NOTES], "called from a.jcw:40:5");
+    }
+}
@@ -1,142 +1,197 @@
-use regex::Regex;
-
-use crate::lib::{BuiltIns, DataStore};
-use crate::lib::user_function::UserFunction;
+use crate::{BuiltIns, DataStore, ErrorKind, MyLangError, Record, Scope, Span, StructType, Value, Variable};
+use std::cell::RefCell;
+use crate::user_function::{Closure, UserFunction};
 use std::collections::HashMap;
+use std::rc::Rc;
 
+// an exression can be a literal - 1, -4, 2.5, true, "text". see value.rs
+// or a built in func - see built_in_functions.rs
+// or a user func - as defined by `func func_name (v a r s) {`. must have been declared prior to evaluation of its call.
+// one defined inside another function is passed copies of the variables it captured from it
+// or a function as a value - a user func's name without its arguments, `@func_name` for one that takes none,
+// or `func a r g s { LINES }` without a name
+// or a call to a function value - f x
+// or a list - [1 2 x]. a new list is made each time it is evaluated
+// or a map - [k: v "a": 1], or [:] for an empty one. also made anew each time it's evaluated
+// or an index into a list or map - xs[i]
+// or one of a struct's fields - p.x
+// or a new struct, which is what the function a struct declaration gives evaluates
+// else a variable name
 #[derive(Debug)]
 pub enum Expression<'a> {
-    Literal(i64),
-    Variable(&'a str),
-    BuiltInFunction(Box<BuiltIns<'a>>),
-    UserFunction(&'a str, Vec<Expression<'a>>),
-    AppliedUserFunction(&'a UserFunction<'a>, Vec<Expression<'a>>)
+    Literal(Value<'a>, Span),
+    Variable(Variable<'a>, Span),
+    List(Vec<Expression<'a>>, Span),
+    Map(Vec<(Expression<'a>, Expression<'a>)>, Span),
+    Index(Box<Expression<'a>>, Box<Expression<'a>>, Span),
+    Field(Box<Expression<'a>>, &'a str, Span),
+    Struct(Rc<StructType<'a>>, Vec<Expression<'a>>, Span),
+    BuiltInFunction(Box<BuiltIns<'a>>, Span),
+    UserFunction(&'a str, Vec<Expression<'a>>, Span),
+    AppliedUserFunction(&'a UserFunction<'a>, Vec<Expression<'a>>, Vec<Variable<'a>>, Span),
+    FunctionValue(&'a str, Span),
+    Lambda(Box<UserFunction<'a>>, Span),
+    // a function value along with the variables it captures when it's made
+    Function(&'a UserFunction<'a>, Vec<Variable<'a>>, Span),
+    Call(Box<Expression<'a>>, Vec<Expression<'a>>, Span),
 }
 
 impl<'a> Expression<'a> {
-    // an exression can be a literal - 1, 3, -4. any valid i64
-    // or a built in func - see built_in_functions.rs
-    // or a user func - as defined by `func func_name (v a r s) {`. must have been declared prior to evaluation of its call
-    // else assumed to be a variable name
-    pub fn parse(expression: &'a str, user_fns: &HashMap<&'a str, UserFunction<'a>>) -> Option<Expression<'a>> {
-        let literal_regex = Regex::new(r"^(-?\d+)$").unwrap();
-
-        let expression = Expression::remove_outer_brackets(expression);
-
-        if let Some(capture) = literal_regex.captures(&expression) {
-            let val = capture[1].parse().unwrap();
-            Some(Expression::Literal(val))
-        } else if let Some(built_in) = BuiltIns::get_function(&expression, user_fns) {
-            Some(Expression::BuiltInFunction(Box::from(built_in)))
-        } else if let Some(user_fn) = is_user_function_call(expression, user_fns) {
-            let space_index = expression.find(" ").unwrap();
-            let args = expression[space_index..].trim();
-            let args = Expression::evaluate_arguments(args, user_fns);
-            Some(Expression::UserFunction(user_fn, args))
-        } else {
-            Some(Expression::Variable(expression))
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Literal(_, span)
+            | Expression::Variable(_, span)
+            | Expression::List(_, span)
+            | Expression::Map(_, span)
+            | Expression::Index(_, _, span)
+            | Expression::Field(_, _, span)
+            | Expression::Struct(_, _, span)
+            | Expression::BuiltInFunction(_, span)
+            | Expression::UserFunction(_, _, span)
+            | Expression::AppliedUserFunction(_, _, _, span)
+            | Expression::FunctionValue(_, span)
+            | Expression::Lambda(_, span)
+            | Expression::Function(_, _, span)
+            | Expression::Call(_, _, span) => *span,
         }
     }
 
     // take an expression and find its value
-    pub fn evaluate(&self, data_store: &mut DataStore<'a>) -> Option<i64> {
+    pub fn evaluate(&self, data_store: &mut DataStore<'a>) -> Result<Value<'a>, MyLangError> {
         match self {
-            Expression::Literal(literal) => Some(*literal),
-            Expression::Variable(variable) => data_store.get(variable),
-            Expression::BuiltInFunction(operation) => operation.apply(data_store),
-            Expression::UserFunction(_func, _args) => {
-                panic!("optimisation step should remove str functions");
-            }
-            Expression::AppliedUserFunction(func, args) => {
-                func.apply(args, data_store)
+            Expression::Literal(literal, _) => Ok(literal.clone()),
+            Expression::Variable(variable, span) => data_store.get(*variable).map_err(|e| e.at(*span)),
+            Expression::List(items, _) => {
+                let items = items.iter()
+                    .map(|item| item.evaluate(data_store))
+                    .collect::<Result<_, MyLangError>>()?;
+                Ok(Value::list(items))
             }
-        }
-    }
-
-    // takes a string and seperates it into its individual expressions. these are then individually parsed
-    // "1 (+ 2 3) 4" => ["1", "(+ 2 3)", "4"]
-    pub fn evaluate_arguments(args: &'a str, user_fns: &HashMap<&'a str, UserFunction<'a>>) -> Vec<Expression<'a>> {
-        let mut res: Vec<Expression> = Vec::new();
-        let mut brackets = 0;
-        let mut start = 0;
-        let mut end = 0;
-
-        const OPEN_BRACKET: u8 = '(' as u8;
-        const CLOSE_BRACKET: u8 = ')' as u8;
-        const SPACE: u8 = ' ' as u8;
-
-        let bytes = args.as_bytes();
-
-        while end < args.len() {
-            match bytes[end] {
-                OPEN_BRACKET => brackets += 1,
-                CLOSE_BRACKET => brackets -= 1,
-                SPACE => {
-                    if brackets == 0 {
-                        if bytes[start] == OPEN_BRACKET && bytes[end] == CLOSE_BRACKET {
-                            start += 1;
-                            end -= 1;
-                        }
-                        let expr = Expression::parse(&args[start..end], user_fns).unwrap();
-                        res.push(expr);
-                        start = end;
-                    }
+            Expression::Map(entries, _) => {
+                let mut map = HashMap::new();
+                for (key, val) in entries {
+                    let k = key.evaluate(data_store)?.as_key("map").map_err(|e| e.at(key.span()))?;
+                    map.insert(k, val.evaluate(data_store)?);
                 }
-                _ => (),
+                Ok(Value::map(map))
+            }
+            Expression::Index(list, index, span) => {
+                let list = list.evaluate(data_store)?;
+                let index = index.evaluate(data_store)?;
+                list.index(&index).map_err(|e| e.at(*span))
+            }
+            Expression::Field(record, field, span) => record.evaluate(data_store)?.field(field).map_err(|e| e.at(*span)),
+            Expression::Struct(kind, fields, _) => {
+                let fields = fields.iter()
+                    .map(|field| field.evaluate(data_store))
+                    .collect::<Result<_, MyLangError>>()?;
+                Ok(Value::Struct(Rc::new(Record { kind: Rc::clone(kind), fields: RefCell::new(fields) })))
+            }
+            Expression::BuiltInFunction(operation, span) => {
+                data_store.check_stack().map_err(|e| e.at(*span))?;
+                operation.apply(data_store)
+                    .map_err(|e| e.at(*span))?
+                    .ok_or_else(|| ErrorKind::NoValue(operation.name().to_string()).at(*span))
+            }
+            Expression::UserFunction(func, _args, span) => Err(ErrorKind::UndefinedFunction(String::from(*func)).at(*span)),
+            Expression::AppliedUserFunction(func, args, captured, span) => func.apply(args, captured, data_store, *span),
+            Expression::FunctionValue(func, span) => Err(ErrorKind::UndefinedFunction(String::from(*func)).at(*span)),
+            Expression::Lambda(func, span) => Err(ErrorKind::UndefinedFunction(String::from(func.name)).at(*span)),
+            Expression::Function(func, captured, _) => {
+                let captured = captured.iter()
+                    .map(|&var| data_store.get(var))
+                    .collect::<Result<_, MyLangError>>()?;
+                Ok(Value::Function(Rc::new(Closure { func, captured })))
+            }
+            Expression::Call(func, args, span) => {
+                let func = func.evaluate(data_store)?;
+                let func = func.as_function("call").map_err(|e| e.at(*span))?;
+                let args = args.iter()
+                    .map(|arg| arg.evaluate(data_store))
+                    .collect::<Result<_, MyLangError>>()?;
+                func.call(args, data_store, *span)
             }
-            end += 1;
-        }
-
-        // something left to do
-        if start != end {
-            let expr = Expression::parse(&args[start..], user_fns).unwrap();
-            res.push(expr);
-        }
-
-        res
-    }
-
-    // "(+ 2 3)" becomes "+ 2 3"
-    fn remove_outer_brackets(expr: &'a str) -> &'a str {
-        let mut expr = expr.trim();
-        let first = expr.chars().nth(0).unwrap();
-        let last = expr.chars().last().unwrap();
-        if first == '(' && last == ')' {
-            expr = &expr[1..expr.len() - 1];
         }
-        expr.trim()
     }
 
-    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>) -> Expression<'a> {
+    // run an expression for its side effects, where it doesn't matter if it has no value
+    pub fn execute(&self, data_store: &mut DataStore<'a>) -> Result<(), MyLangError> {
         match self {
-            Expression::Literal(i) => Expression::Literal(*i),
-            Expression::Variable(var) => Expression::Variable(var),
-            Expression::BuiltInFunction(func) => Expression::BuiltInFunction(Box::from(func.optimise(user_fns))),
-            Expression::UserFunction(f_name, args) => {
-                let func = user_fns.get(f_name).unwrap();
-                let args = args.iter().map(|arg| arg.optimise(user_fns)).collect();
-                Expression::AppliedUserFunction(func, args)
-            }
-            Expression::AppliedUserFunction(func , args) => {
-                let args = args.iter().map(|arg| arg.optimise(user_fns)).collect();
-                Expression::AppliedUserFunction(func , args)
+            Expression::BuiltInFunction(operation, span) => {
+                data_store.check_stack().map_err(|e| e.at(*span))?;
+                operation.apply(data_store)
+                    .map(|_| ())
+                    .map_err(|e| e.at(*span))
             }
+            _ => self.evaluate(data_store).map(|_| ()),
         }
     }
-}
 
-// checks map of user functions for one by the name of the first word of the line. if it's there, extract its name
-fn is_user_function_call<'a>(line: &'a str, user_fns: &HashMap<&'a str, UserFunction<'a>>) -> Option<&'a str> {
-    match line.find(' ') {
-        Some(ind) => {
-            let f_name = &line[..ind];
-            for &func_name in user_fns.keys() {
-                if func_name.eq(f_name) {
-                    return Some(func_name)
+    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &mut Scope<'a>) -> Result<Expression<'a>, MyLangError> {
+        let expression = match self {
+            Expression::Literal(val, span) => Expression::Literal(val.clone(), *span),
+            Expression::Variable(var, span) => Expression::Variable(scope.resolve(*var, *span)?, *span),
+            Expression::List(items, span) => Expression::List(Expression::optimise_all(items, user_fns, scope)?, *span),
+            Expression::Map(entries, span) => {
+                let entries = entries.iter()
+                    .map(|(key, val)| Ok((key.optimise(user_fns, scope)?, val.optimise(user_fns, scope)?)))
+                    .collect::<Result<_, MyLangError>>()?;
+                Expression::Map(entries, *span)
+            }
+            Expression::Index(list, index, span) => {
+                Expression::Index(Box::from(list.optimise(user_fns, scope)?), Box::from(index.optimise(user_fns, scope)?), *span)
+            }
+            Expression::Field(record, field, span) => Expression::Field(Box::from(record.optimise(user_fns, scope)?), field, *span),
+            Expression::Struct(kind, fields, span) => {
+                Expression::Struct(Rc::clone(kind), Expression::optimise_all(fields, user_fns, scope)?, *span)
+            }
+            Expression::BuiltInFunction(func, span) => Expression::BuiltInFunction(Box::from(func.optimise(user_fns, scope)?), *span),
+            // a variable hides any function with the same name, and functions defined inside the current one hide
+            // those declared outside of any
+            Expression::UserFunction(f_name, args, span) if scope.can_see(f_name) => {
+                let func = Expression::Variable(scope.resolve(Variable::new(f_name), *span)?, *span);
+                match args.len() {
+                    0 => func,
+                    _ => Expression::Call(Box::from(func), Expression::optimise_all(args, user_fns, scope)?, *span),
                 }
             }
-            return None
-        }
-        _ => None
+            Expression::UserFunction(f_name, args, span) => {
+                let (func, captured) = match scope.function_named(f_name) {
+                    Some((func, captured)) => (func, captured.to_vec()),
+                    None => {
+                        let func = user_fns.get(f_name)
+                            .ok_or_else(|| ErrorKind::UndefinedFunction(String::from(*f_name)).at(*span))?;
+                        (func, Vec::new())
+                    }
+                };
+                Expression::AppliedUserFunction(func, Expression::optimise_all(args, user_fns, scope)?, captured, *span)
+            }
+            Expression::AppliedUserFunction(func , args, captured, span) => {
+                Expression::AppliedUserFunction(func , Expression::optimise_all(args, user_fns, scope)?, captured.clone(), *span)
+            }
+            Expression::FunctionValue(f_name, span) if scope.can_see(f_name) => {
+                Expression::Variable(scope.resolve(Variable::new(f_name), *span)?, *span)
+            }
+            Expression::FunctionValue(f_name, span) => match scope.function_named(f_name) {
+                Some((func, captured)) => Expression::Function(func, captured.to_vec(), *span),
+                None => {
+                    let func = user_fns.get(f_name)
+                        .ok_or_else(|| ErrorKind::UndefinedFunction(String::from(*f_name)).at(*span))?;
+                    Expression::Function(func, Vec::new(), *span)
+                }
+            },
+            Expression::Lambda(func, span) => Expression::Function(func, func.optimise_lambda(user_fns, scope)?, *span),
+            Expression::Function(func, captured, span) => Expression::Function(func, captured.clone(), *span),
+            Expression::Call(func, args, span) => {
+                Expression::Call(Box::from(func.optimise(user_fns, scope)?), Expression::optimise_all(args, user_fns, scope)?, *span)
+            }
+        };
+        Ok(expression)
+    }
+
+    pub fn optimise_all(expressions: &'a [Expression<'a>], user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &mut Scope<'a>) -> Result<Vec<Expression<'a>>, MyLangError> {
+        expressions.iter()
+            .map(|expression| expression.optimise(user_fns, scope))
+            .collect()
     }
-}
\ No newline at end of file
+}
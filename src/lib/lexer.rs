@@ -0,0 +1,363 @@
+use crate::{ErrorKind, MyLangError, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind<'a> {
+    Int(&'a str),
+    Float(&'a str),
+    // the text between the quotes, with any escapes still in it
+    Str(&'a str),
+    Bool(bool),
+    Ident(&'a str),
+    // operators like `+` and `==`. these name built in functions
+    Operator(&'a str),
+    Func,
+    Let,
+    Struct,
+    Enum,
+    Match,
+    Import,
+    If,
+    Elif,
+    Else,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
+    Return,
+    OpenBracket,
+    CloseBracket,
+    OpenBrace,
+    CloseBrace,
+    OpenSquare,
+    CloseSquare,
+    Colon,
+    Semicolon,
+    // `...`, which comes before a function's rest argument
+    Ellipsis,
+    // the `.` between a struct and one of its fields
+    Dot,
+    // `..`, between the ends of a range in a match pattern
+    DotDot,
+    // the `@` in front of a function name that makes it the function as a value
+    At,
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub span: Span,
+}
+
+const OPERATORS: [&str; 19] = ["==", "!=", "<<", ">>", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "?", "!", "&", "|", "^", "~"];
+
+// splits program text into tokens. newlines end statements, except inside brackets or a list where an
+// expression may be split over several lines. they end statements again inside a block in brackets, like
+// the code of a function without a name passed as an argument. comments are skipped, `#` and `//` run to
+// the end of the line and `/* */` can cover several lines, ending a statement like a newline would if it does
+pub fn tokenise(text: &str, file: u32) -> Result<Vec<Token<'_>>, MyLangError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_start = 0;
+    // the brackets, lists and blocks the current token is inside
+    let mut nesting = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let span = move |end: usize| Span {
+            file,
+            line,
+            column: (start - line_start) as u32,
+            len: (end - start) as u32,
+        };
+
+        let kind = match bytes[i] {
+            b'\n' => {
+                i += 1;
+                let newline = span(i);
+                line += 1;
+                line_start = i;
+                if matches!(nesting.last(), None | Some(b'{')) {
+                    tokens.push(Token { kind: TokenKind::Newline, span: newline });
+                }
+                continue;
+            }
+            b' ' | b'\t' | b'\r' => {
+                i += 1;
+                continue;
+            }
+            b'#' | b'/' if bytes[i] == b'#' || text[i..].starts_with("//") => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if text[i..].starts_with("/*") => {
+                match text[i + 2..].find("*/") {
+                    Some(end) => {
+                        let comment = &text[i..i + 2 + end];
+                        i += 2 + end + 2;
+                        if let Some(last) = comment.rfind('\n') {
+                            let newline = span(i);
+                            line += comment.matches('\n').count() as u32;
+                            line_start = start + last + 1;
+                            if matches!(nesting.last(), None | Some(b'{')) {
+                                tokens.push(Token { kind: TokenKind::Newline, span: newline });
+                            }
+                        }
+                    }
+                    None => return Err(ErrorKind::UnclosedComment.at(span(start + 2))),
+                }
+                continue;
+            }
+            b'(' | b'[' => {
+                nesting.push(bytes[i]);
+                i += 1;
+                if bytes[start] == b'(' { TokenKind::OpenBracket } else { TokenKind::OpenSquare }
+            }
+            b')' | b']' => {
+                nesting.pop();
+                i += 1;
+                if bytes[start] == b')' { TokenKind::CloseBracket } else { TokenKind::CloseSquare }
+            }
+            b'{' => {
+                nesting.push(bytes[i]);
+                i += 1;
+                TokenKind::OpenBrace
+            }
+            b'}' => {
+                nesting.pop();
+                i += 1;
+                TokenKind::CloseBrace
+            }
+            b':' => {
+                i += 1;
+                TokenKind::Colon
+            }
+            b';' => {
+                i += 1;
+                TokenKind::Semicolon
+            }
+            b'.' if text[i..].starts_with("...") => {
+                i += 3;
+                TokenKind::Ellipsis
+            }
+            b'.' if text[i..].starts_with("..") => {
+                i += 2;
+                TokenKind::DotDot
+            }
+            b'.' => {
+                i += 1;
+                TokenKind::Dot
+            }
+            b'@' => {
+                i += 1;
+                TokenKind::At
+            }
+            // a minus sign directly in front of a number is a negative literal, unless it comes straight after
+            // something that could be the operand of an infix operator, so `a-1` and `a - 1` are subtractions
+            // while `(-1)` and the argument in `abs -1` are literals. `- 1 2` is a subtraction too.
+            // a number with a decimal point and digits either side of it is a float
+            b'-' | b'0'..=b'9' if bytes[i].is_ascii_digit() || (
+                bytes.get(i + 1).is_some_and(u8::is_ascii_digit)
+                    && (!tokens.last().is_some_and(|prev: &Token| prev.kind.ends_operand()) || bytes[..i].last().is_some_and(u8::is_ascii_whitespace))
+            ) => {
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                if bytes.get(i) == Some(&b'.') && bytes.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    i += 1;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                    TokenKind::Float(&text[start..i])
+                } else {
+                    TokenKind::Int(&text[start..i])
+                }
+            }
+            // strings can't go over multiple lines, use `\n` instead
+            b'"' => {
+                i += 1;
+                loop {
+                    match bytes.get(i) {
+                        Some(b'"') => break,
+                        Some(b'\\') => {
+                            match bytes.get(i + 1) {
+                                Some(b'n') | Some(b't') | Some(b'\\') | Some(b'"') => i += 2,
+                                _ => {
+                                    let escape = text[i..].chars().take(2).collect::<String>();
+                                    let bad = Span { column: (i - line_start) as u32, len: escape.len() as u32, ..span(i) };
+                                    return Err(ErrorKind::Parse(escape).at(bad));
+                                }
+                            }
+                        }
+                        Some(b'\n') | None => return Err(ErrorKind::UnclosedString.at(span(start + 1))),
+                        Some(_) => i += 1,
+                    }
+                }
+                i += 1;
+                TokenKind::Str(&text[start + 1..i - 1])
+            }
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                match &text[start..i] {
+                    "func" => TokenKind::Func,
+                    "let" => TokenKind::Let,
+                    "struct" => TokenKind::Struct,
+                    "enum" => TokenKind::Enum,
+                    "match" => TokenKind::Match,
+                    "import" => TokenKind::Import,
+                    "if" => TokenKind::If,
+                    "elif" => TokenKind::Elif,
+                    "else" => TokenKind::Else,
+                    "while" => TokenKind::While,
+                    "for" => TokenKind::For,
+                    "in" => TokenKind::In,
+                    "break" => TokenKind::Break,
+                    "continue" => TokenKind::Continue,
+                    "return" => TokenKind::Return,
+                    "true" => TokenKind::Bool(true),
+                    "false" => TokenKind::Bool(false),
+                    ident => TokenKind::Ident(ident),
+                }
+            }
+            _ => match OPERATORS.iter().find(|op| text[i..].starts_with(*op)) {
+                Some(op) => {
+                    i += op.len();
+                    TokenKind::Operator(op)
+                }
+                None => {
+                    let c = text[i..].chars().next().unwrap();
+                    return Err(ErrorKind::Parse(c.to_string()).at(span(i + c.len_utf8())));
+                }
+            },
+        };
+        tokens.push(Token { kind, span: span(i) });
+    }
+
+    let end = Span {
+        file,
+        line,
+        column: (bytes.len() - line_start) as u32,
+        len: 0,
+    };
+    tokens.push(Token { kind: TokenKind::Eof, span: end });
+    Ok(tokens)
+}
+
+// the text of a string literal with its escapes replaced. the lexer has already checked they're valid
+pub fn unescape(raw: &str) -> String {
+    let mut text = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some(c) => text.push(c),
+            None => (),
+        }
+    }
+    text
+}
+
+impl<'a> TokenKind<'a> {
+    // whether the token can be the last one of the left hand side of an infix operator
+    pub fn ends_operand(&self) -> bool {
+        matches!(self, TokenKind::Int(_) | TokenKind::Float(_) | TokenKind::Str(_) | TokenKind::Bool(_) | TokenKind::Ident(_)
+            | TokenKind::CloseBracket | TokenKind::CloseSquare | TokenKind::CloseBrace)
+    }
+
+    // how the token is described in error messages
+    pub fn describe(&self) -> String {
+        match self {
+            TokenKind::Int(text) | TokenKind::Float(text) | TokenKind::Ident(text) | TokenKind::Operator(text) => format!("\"{}\"", text),
+            TokenKind::Str(text) => format!("string \"{}\"", text),
+            TokenKind::Bool(b) => format!("\"{}\"", b),
+            TokenKind::Func => String::from("\"func\""),
+            TokenKind::Let => String::from("\"let\""),
+            TokenKind::Struct => String::from("\"struct\""),
+            TokenKind::Enum => String::from("\"enum\""),
+            TokenKind::Match => String::from("\"match\""),
+            TokenKind::Import => String::from("\"import\""),
+            TokenKind::If => String::from("\"if\""),
+            TokenKind::Elif => String::from("\"elif\""),
+            TokenKind::Else => String::from("\"else\""),
+            TokenKind::While => String::from("\"while\""),
+            TokenKind::For => String::from("\"for\""),
+            TokenKind::In => String::from("\"in\""),
+            TokenKind::Break => String::from("\"break\""),
+            TokenKind::Continue => String::from("\"continue\""),
+            TokenKind::Return => String::from("\"return\""),
+            TokenKind::OpenBracket => String::from("\"(\""),
+            TokenKind::CloseBracket => String::from("\")\""),
+            TokenKind::OpenBrace => String::from("\"{\""),
+            TokenKind::CloseBrace => String::from("\"}\""),
+            TokenKind::OpenSquare => String::from("\"[\""),
+            TokenKind::CloseSquare => String::from("\"]\""),
+            TokenKind::Colon => String::from("\":\""),
+            TokenKind::Semicolon => String::from("\";\""),
+            TokenKind::Ellipsis => String::from("\"...\""),
+            TokenKind::Dot => String::from("\".\""),
+            TokenKind::DotDot => String::from("\"..\""),
+            TokenKind::At => String::from("\"@\""),
+            TokenKind::Newline => String::from("end of line"),
+            TokenKind::Eof => String::from("end of file"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(text: &str) -> Vec<TokenKind<'_>> {
+        tokenise(text, 0).unwrap().into_iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn minus_touching_an_operand_is_a_subtraction() {
+        assert_eq!(kinds("a-1"), [TokenKind::Ident("a"), TokenKind::Operator("-"), TokenKind::Int("1"), TokenKind::Eof]);
+        assert_eq!(kinds("(a)-1.5"), [
+            TokenKind::OpenBracket, TokenKind::Ident("a"), TokenKind::CloseBracket,
+            TokenKind::Operator("-"), TokenKind::Float("1.5"), TokenKind::Eof,
+        ]);
+    }
+
+    #[test]
+    fn spaced_minus_is_a_subtraction() {
+        assert_eq!(kinds("a - 1"), [TokenKind::Ident("a"), TokenKind::Operator("-"), TokenKind::Int("1"), TokenKind::Eof]);
+    }
+
+    #[test]
+    fn minus_where_no_operand_can_end_is_a_negative_literal() {
+        assert_eq!(kinds("(-1)"), [TokenKind::OpenBracket, TokenKind::Int("-1"), TokenKind::CloseBracket, TokenKind::Eof]);
+        assert_eq!(kinds("-1"), [TokenKind::Int("-1"), TokenKind::Eof]);
+        assert_eq!(kinds("x: -1"), [TokenKind::Ident("x"), TokenKind::Colon, TokenKind::Int("-1"), TokenKind::Eof]);
+        assert_eq!(kinds("[-1]"), [TokenKind::OpenSquare, TokenKind::Int("-1"), TokenKind::CloseSquare, TokenKind::Eof]);
+        assert_eq!(kinds("a\n-1"), [TokenKind::Ident("a"), TokenKind::Newline, TokenKind::Int("-1"), TokenKind::Eof]);
+        assert_eq!(kinds("a*-1"), [TokenKind::Ident("a"), TokenKind::Operator("*"), TokenKind::Int("-1"), TokenKind::Eof]);
+    }
+
+    #[test]
+    fn block_comment_over_several_lines_ends_a_statement() {
+        assert_eq!(kinds("a /* x\n\ny */ b"), [TokenKind::Ident("a"), TokenKind::Newline, TokenKind::Ident("b"), TokenKind::Eof]);
+        assert_eq!(kinds("a /* x */ b"), [TokenKind::Ident("a"), TokenKind::Ident("b"), TokenKind::Eof]);
+        assert_eq!(kinds("(a /* x\n */ b)"), [TokenKind::OpenBracket, TokenKind::Ident("a"), TokenKind::Ident("b"), TokenKind::CloseBracket, TokenKind::Eof]);
+    }
+
+    #[test]
+    fn minus_starting_an_argument_is_a_negative_literal() {
+        assert_eq!(kinds("abs -1"), [TokenKind::Ident("abs"), TokenKind::Int("-1"), TokenKind::Eof]);
+    }
+}
@@ -1,14 +1,31 @@
+pub use bigint::BigInt;
 pub use built_in_functions::BuiltIns;
-pub use constructs::Construct;
-pub use data_store::DataStore;
+pub use constructs::{Construct, Pattern};
+pub use data_store::{DataStore, MAX_CALL_DEPTH, STACK_SIZE};
+pub use error::{ErrorKind, MyLangError};
 pub use expression::Expression;
+pub use modules::load_imports;
+pub use overflow::Overflow;
+pub use program::Flow;
 pub use program::Line;
 pub use program::Program;
-pub use program::get_sub_program;
+pub use scope::{Scope, Slot, Variable};
+pub use source::{Source, Sources, Span};
+pub use user_function::UserFunction;
+pub use value::{Key, Record, StructType, Value};
 
+mod bigint;
 mod built_in_functions;
 mod constructs;
 mod data_store;
+mod error;
 mod expression;
+mod lexer;
+mod modules;
+mod overflow;
+mod parser;
 mod program;
-mod user_function;
\ No newline at end of file
+mod scope;
+mod source;
+mod user_function;
+mod value;
\ No newline at end of file
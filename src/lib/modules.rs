@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{ErrorKind, MyLangError, Sources, Span};
+use crate::lexer::{tokenise, unescape, TokenKind};
+
+// reads in the files a program imports, and the files they import, each only once however many files
+// import it. an import is looked for relative to the file importing it, then in each directory of the
+// search path in turn
+struct Modules<'s> {
+    sources: &'s mut Sources,
+    search_path: Vec<PathBuf>,
+    // the files read in so far, by their canonical path
+    loaded: HashMap<PathBuf, u32>,
+    // the files whose imports are being read in, each one imported by the one before it
+    loading: Vec<u32>,
+    // the files whose imports have all been read in, each after the files it imports
+    order: Vec<u32>,
+}
+
+// every file the program `file` needs, ending with itself, with each file after the files it imports.
+// `path` is where the program was read from, if it came from a file, otherwise its imports are looked
+// for relative to the current directory
+pub fn load_imports(sources: &mut Sources, file: u32, path: Option<&Path>, search_path: Vec<PathBuf>) -> Result<Vec<u32>, MyLangError> {
+    let mut modules = Modules {
+        sources,
+        search_path,
+        loaded: HashMap::new(),
+        loading: vec![file],
+        order: Vec::new(),
+    };
+    if let Some(canonical) = path.and_then(|path| fs::canonicalize(path).ok()) {
+        modules.loaded.insert(canonical, file);
+    }
+    let dir = path.and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+    modules.load(file, dir)?;
+    Ok(modules.order)
+}
+
+impl Modules<'_> {
+    fn load(&mut self, file: u32, dir: &Path) -> Result<(), MyLangError> {
+        for (import, span) in imports(&self.sources.get(file).text, file)? {
+            let not_found = || ErrorKind::Import { path: import.clone(), reason: String::from("file not found") }.at(span);
+            let path = self.find(dir, &import).ok_or_else(not_found)?;
+            let canonical = fs::canonicalize(&path).map_err(|_| not_found())?;
+            if let Some(&loaded) = self.loaded.get(&canonical) {
+                // a file that's still having its imports read in is importing itself
+                if let Some(i) = self.loading.iter().position(|&f| f == loaded) {
+                    let cycle = self.loading[i..].iter()
+                        .chain(std::iter::once(&loaded))
+                        .map(|&f| self.sources.get(f).name.clone())
+                        .collect();
+                    return Err(ErrorKind::ImportCycle(cycle).at(span));
+                }
+                continue;
+            }
+
+            let text = fs::read_to_string(&path)
+                .map_err(|err| ErrorKind::Import { path: import.clone(), reason: err.to_string() }.at(span))?;
+            let imported = self.sources.add(path.display().to_string(), text);
+            self.loaded.insert(canonical, imported);
+            self.loading.push(imported);
+            let loaded = self.load(imported, path.parent().unwrap_or_else(|| Path::new("")));
+            self.loading.pop();
+            loaded?;
+        }
+        self.order.push(file);
+        Ok(())
+    }
+
+    fn find(&self, dir: &Path, import: &str) -> Option<PathBuf> {
+        std::iter::once(dir)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(import))
+            .find(|path| path.is_file())
+    }
+}
+
+// the paths of the `import "PATH"` lines of a file, along with where they are. the parser checks they're
+// at the top level of the file once it's read in
+fn imports(text: &str, file: u32) -> Result<Vec<(String, Span)>, MyLangError> {
+    let tokens = tokenise(text, file)?;
+    let imports = tokens.windows(2)
+        .filter_map(|pair| match (pair[0].kind, pair[1].kind) {
+            (TokenKind::Import, TokenKind::Str(path)) => Some((unescape(path), pair[0].span.to(pair[1].span))),
+            _ => None,
+        })
+        .collect();
+    Ok(imports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a directory of its own for a test, holding `files`
+    fn directory(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("my_lang_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (name, text) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    // the names of the files the program `main.jcw` in `dir` needs, in the order they're run
+    fn load(dir: &Path, search_path: Vec<PathBuf>) -> Result<Vec<String>, MyLangError> {
+        let mut sources = Sources::new();
+        let path = dir.join("main.jcw");
+        let file = sources.add(String::from("main.jcw"), fs::read_to_string(&path).unwrap());
+        let files = load_imports(&mut sources, file, Some(&path), search_path)?;
+        Ok(files.iter()
+            .map(|&f| Path::new(&sources.get(f).name).file_name().unwrap().to_string_lossy().into_owned())
+            .collect())
+    }
+
+    #[test]
+    fn imports_are_read_once_each_after_the_files_they_import() {
+        let dir = directory("once", &[
+            ("main.jcw", "import \"lib/shapes.jcw\"\nimport \"lib/maths.jcw\"\n"),
+            ("lib/shapes.jcw", "import \"maths.jcw\"\n"),
+            ("lib/maths.jcw", ""),
+        ]);
+        assert_eq!(load(&dir, Vec::new()).unwrap(), ["maths.jcw", "shapes.jcw", "main.jcw"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn imports_are_looked_for_next_to_the_importing_file_then_on_the_search_path() {
+        let dir = directory("search", &[
+            ("main.jcw", "import \"a.jcw\"\nimport \"b.jcw\"\n"),
+            ("a.jcw", ""),
+            ("first/a.jcw", "import \"nowhere.jcw\"\n"),
+            ("first/b.jcw", ""),
+            ("second/b.jcw", "import \"nowhere.jcw\"\n"),
+        ]);
+        assert_eq!(load(&dir, vec![dir.join("first"), dir.join("second")]).unwrap(), ["a.jcw", "b.jcw", "main.jcw"]);
+        let error = load(&dir, vec![dir.join("second")]).unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::Import { path: String::from("nowhere.jcw"), reason: String::from("file not found") });
+        assert_eq!(error.span(), Some(Span { file: 2, line: 1, column: 0, len: 20 }));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn files_importing_each_other_are_an_error() {
+        let dir = directory("cycle", &[
+            ("main.jcw", "import \"a.jcw\"\n"),
+            ("a.jcw", "import \"b.jcw\"\n"),
+            ("b.jcw", "\nimport \"a.jcw\"\n"),
+        ]);
+        let error = load(&dir, Vec::new()).unwrap_err();
+        match error.kind() {
+            ErrorKind::ImportCycle(files) => {
+                let names = files.iter().map(|f| Path::new(f).file_name().unwrap().to_str().unwrap()).collect::<Vec<_>>();
+                assert_eq!(names, ["a.jcw", "b.jcw", "a.jcw"]);
+            }
+            kind => panic!("expected an import cycle, not {:?}", kind),
+        }
+        assert_eq!(error.span(), Some(Span { file: 2, line: 2, column: 0, len: 14 }));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn an_import_inside_a_block_is_an_error() {
+        let error = crate::program::tests::run("if true {\n    import \"a.jcw\"\n}").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::NotTopLevel(String::from("import")));
+        assert_eq!(error.span(), Some(Span { file: 0, line: 2, column: 4, len: 6 }));
+    }
+}
@@ -0,0 +1,304 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use crate::{BigInt, ErrorKind, MyLangError, Value};
+
+// what happens when int arithmetic gives a result that doesn't fit in an int. the same policy is used by
+// every built in, so a program gives the same results however the interpreter was built
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    // carry on with a big int, which is the default
+    #[default]
+    Promote,
+    // stop the program with an error
+    Error,
+    // wrap around, keeping the lowest 64 bits of the two's complement result
+    Wrap,
+    // give back the int closest to the result
+    Saturate,
+}
+
+// the most bits a big int given by a power or a left shift can have. big ints are multiplied one digit by
+// another, which takes time growing with the square of their size, so one this size takes around half a
+// second to work out, and a few more to print
+const MAX_POW_BITS: u64 = 1 << 20;
+
+// an arithmetic operator, as it's done on two ints under each policy, on big ints and on floats
+pub struct Arithmetic {
+    pub checked: fn(i64, i64) -> Option<i64>,
+    pub wrapping: fn(i64, i64) -> i64,
+    pub saturating: fn(i64, i64) -> i64,
+    pub big: fn(&BigInt, &BigInt) -> BigInt,
+    pub floats: fn(f64, f64) -> f64,
+}
+
+pub const ADD: Arithmetic = Arithmetic {
+    checked: i64::checked_add,
+    wrapping: i64::wrapping_add,
+    saturating: i64::saturating_add,
+    big: |a, b| a + b,
+    floats: |a, b| a + b,
+};
+
+pub const SUB: Arithmetic = Arithmetic {
+    checked: i64::checked_sub,
+    wrapping: i64::wrapping_sub,
+    saturating: i64::saturating_sub,
+    big: |a, b| a - b,
+    floats: |a, b| a - b,
+};
+
+pub const MUL: Arithmetic = Arithmetic {
+    checked: i64::checked_mul,
+    wrapping: i64::wrapping_mul,
+    saturating: i64::saturating_mul,
+    big: |a, b| a * b,
+    floats: |a, b| a * b,
+};
+
+// division by zero is always an error, and is checked for before any of these are used. the only division
+// that overflows is `i64::MIN` by -1
+pub const DIV: Arithmetic = Arithmetic {
+    checked: i64::checked_div,
+    wrapping: i64::wrapping_div,
+    saturating: i64::saturating_div,
+    big: |a, b| a / b,
+    floats: |a, b| a / b,
+};
+
+// a remainder never overflows. `i64::MIN` by -1 does in `i64::checked_rem`, as it's worked out from a
+// division that does, but it's 0 whatever the policy
+pub const REM: Arithmetic = Arithmetic {
+    checked: |a, b| Some(a.wrapping_rem(b)),
+    wrapping: i64::wrapping_rem,
+    saturating: i64::wrapping_rem,
+    big: |a, b| a % b,
+    floats: |a, b| a % b,
+};
+
+impl Overflow {
+    #[inline]
+    pub fn apply<'a>(self, op: &Arithmetic, a: i64, b: i64) -> Result<Value<'a>, MyLangError> {
+        match self {
+            Overflow::Promote => match (op.checked)(a, b) {
+                Some(n) => Ok(Value::Int(n)),
+                None => Ok(Value::big((op.big)(&a.into(), &b.into()))),
+            },
+            Overflow::Error => (op.checked)(a, b).map(Value::Int).ok_or_else(|| ErrorKind::Overflow.into()),
+            Overflow::Wrap => Ok(Value::Int((op.wrapping)(a, b))),
+            Overflow::Saturate => Ok(Value::Int((op.saturating)(a, b))),
+        }
+    }
+
+    // a result worked out with room to spare, brought back down to an int
+    pub fn narrow<'a>(self, n: i128) -> Result<Value<'a>, MyLangError> {
+        match self {
+            Overflow::Promote => Ok(i64::try_from(n).map(Value::Int).unwrap_or_else(|_| Value::big(n.into()))),
+            Overflow::Error => i64::try_from(n).map(Value::Int).map_err(|_| ErrorKind::Overflow.into()),
+            Overflow::Wrap => Ok(Value::Int(n as i64)),
+            Overflow::Saturate => Ok(Value::Int(n.clamp(i64::MIN.into(), i64::MAX.into()) as i64)),
+        }
+    }
+
+    // a result worked out exactly with big ints. the policy applies to it just as it would if the
+    // arguments had been ints, so once a big int is wrapped or saturated it's an int again
+    pub fn fit<'a>(self, n: BigInt) -> Result<Value<'a>, MyLangError> {
+        match (self, n.to_i64()) {
+            (Overflow::Promote, _) => Ok(Value::big(n)),
+            (_, Some(i)) => Ok(Value::Int(i)),
+            (Overflow::Error, None) => Err(ErrorKind::Overflow.into()),
+            (Overflow::Wrap, None) => Ok(Value::Int(n.wrap_i64())),
+            (Overflow::Saturate, None) => Ok(Value::Int(if n.is_negative() { i64::MIN } else { i64::MAX })),
+        }
+    }
+
+    // `a` shifted left by `bits`, which is `a` times 2 to the power of `bits`. like a power, a big int with
+    // more than `MAX_POW_BITS` bits is an error whatever the policy
+    pub fn shl<'a>(self, a: i64, bits: u64) -> Result<Value<'a>, MyLangError> {
+        if bits < 64 {
+            return self.narrow(i128::from(a) << bits);
+        }
+        match self {
+            _ if a == 0 => Ok(Value::Int(0)),
+            Overflow::Promote if bits <= MAX_POW_BITS => Ok(Value::big(&BigInt::from(a) * &BigInt::from(2i64).pow(bits as u32))),
+            Overflow::Promote | Overflow::Error => Err(ErrorKind::Overflow.into()),
+            // every bit of the int has been shifted out
+            Overflow::Wrap => Ok(Value::Int(0)),
+            Overflow::Saturate => Ok(Value::Int(if a < 0 { i64::MIN } else { i64::MAX })),
+        }
+    }
+
+    // powers of big ints are always worked out exactly, so one with more than `MAX_POW_BITS` bits is an
+    // error whatever the policy, rather than taking forever or running out of memory. big ints are never
+    // 0, 1 or -1, the only ints whose powers don't grow
+    pub fn pow_big<'a>(self, base: &BigInt, exp: u64) -> Result<Value<'a>, MyLangError> {
+        match u32::try_from(exp) {
+            Ok(exp) if base.bits() * u64::from(exp) <= MAX_POW_BITS => self.fit(base.pow(exp)),
+            _ => Err(ErrorKind::Overflow.into()),
+        }
+    }
+
+    pub fn pow<'a>(self, base: i64, exp: u64) -> Result<Value<'a>, MyLangError> {
+        let exact = match u32::try_from(exp) {
+            Ok(exp) => base.checked_pow(exp),
+            // only these can be raised to such a large power without overflowing
+            Err(_) => match base {
+                0 | 1 => Some(base),
+                -1 => Some(if exp.is_multiple_of(2) { 1 } else { -1 }),
+                _ => None,
+            },
+        };
+        match (exact, self) {
+            (Some(result), _) => Ok(Value::Int(result)),
+            (None, Overflow::Promote) => self.pow_big(&base.into(), exp),
+            (None, Overflow::Error) => Err(ErrorKind::Overflow.into()),
+            (None, Overflow::Saturate) if base < 0 && !exp.is_multiple_of(2) => Ok(Value::Int(i64::MIN)),
+            (None, Overflow::Saturate) => Ok(Value::Int(i64::MAX)),
+            (None, Overflow::Wrap) => {
+                let (mut base, mut exp, mut result) = (base, exp, 1i64);
+                while exp > 0 {
+                    if exp & 1 == 1 {
+                        result = result.wrapping_mul(base);
+                    }
+                    base = base.wrapping_mul(base);
+                    exp >>= 1;
+                }
+                Ok(Value::Int(result))
+            }
+        }
+    }
+}
+
+// the policy as it's named on the command line
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "promote" => Ok(Overflow::Promote),
+            "error" => Ok(Overflow::Error),
+            "wrap" => Ok(Overflow::Wrap),
+            "saturate" => Ok(Overflow::Saturate),
+            _ => Err(format!("unknown overflow policy \"{}\", expected promote, error, wrap or saturate", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::tests::run_with_overflow;
+
+    const POLICIES: [Overflow; 4] = [Overflow::Promote, Overflow::Error, Overflow::Wrap, Overflow::Saturate];
+
+    // what `expression` gives under each of the policies, in the order of `POLICIES`
+    fn under_each_policy(expression: &str) -> Vec<Result<String, ErrorKind>> {
+        let text = format!("x: {}", expression.replace("MAX", "9223372036854775807").replace("MIN", "(-9223372036854775808)"));
+        POLICIES.iter()
+            .map(|&overflow| match run_with_overflow(&text, overflow) {
+                Ok(globals) => Ok(globals[0].clone()),
+                Err(error) => Err(error.kind().clone()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn arithmetic_at_the_edges_of_an_int_follows_the_policy() {
+        let ok = |val: &str| Ok(String::from(val));
+        let overflow = Err(ErrorKind::Overflow);
+        let same = |val: &str| [ok(val), ok(val), ok(val), ok(val)];
+        let (max, min) = (ok("9223372036854775807"), ok("-9223372036854775808"));
+        let table = [
+            ("+ MAX 1", [ok("9223372036854775808"), overflow.clone(), min.clone(), max.clone()]),
+            ("+ MIN (-1)", [ok("-9223372036854775809"), overflow.clone(), max.clone(), min.clone()]),
+            ("+ MAX MIN", same("-1")),
+            ("- MIN 1", [ok("-9223372036854775809"), overflow.clone(), max.clone(), min.clone()]),
+            ("- MAX (-1)", [ok("9223372036854775808"), overflow.clone(), min.clone(), max.clone()]),
+            ("- 0 MAX", same("-9223372036854775807")),
+            ("* MAX 2", [ok("18446744073709551614"), overflow.clone(), ok("-2"), max.clone()]),
+            ("* MIN 2", [ok("-18446744073709551616"), overflow.clone(), ok("0"), min.clone()]),
+            ("* MIN (-1)", [ok("9223372036854775808"), overflow.clone(), min.clone(), max.clone()]),
+            ("* 4294967296 4294967296", [ok("18446744073709551616"), overflow.clone(), ok("0"), max.clone()]),
+            ("- MIN", [ok("9223372036854775808"), overflow.clone(), min.clone(), max.clone()]),
+            ("- MAX", same("-9223372036854775807")),
+            ("abs MIN", [ok("9223372036854775808"), overflow.clone(), min.clone(), max.clone()]),
+            ("abs (- 0 MAX)", same("9223372036854775807")),
+            ("pow 2 63", [ok("9223372036854775808"), overflow.clone(), min.clone(), max.clone()]),
+            ("pow (-2) 63", same("-9223372036854775808")),
+            ("pow (-3) 41", [ok("-36472996377170786403"), overflow.clone(), ok("420491770248316829"), min.clone()]),
+            ("pow 3 39", same("4052555153018976267")),
+            ("/ MIN (-1)", [ok("9223372036854775808"), overflow.clone(), min.clone(), max.clone()]),
+            ("% MIN (-1)", same("0")),
+        ];
+        for (expression, expected) in table {
+            assert_eq!(under_each_policy(expression), expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn dividing_by_zero_is_an_error_under_every_policy() {
+        for expression in ["/ 1 0", "% 1 0", "/ MIN 0", "% MAX 0", "/ 0 0", "/ 1.5 0", "% (+ MAX 1) 0"] {
+            let results = under_each_policy(expression);
+            // under the error policy `+ MAX 1` is an overflow before the division is reached
+            let expected = POLICIES.iter()
+                .map(|&overflow| match overflow {
+                    Overflow::Error if expression.contains("+ MAX 1") => Err(ErrorKind::Overflow),
+                    _ => Err(ErrorKind::DivisionByZero),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(results, expected, "{}", expression);
+        }
+    }
+
+    fn shl(overflow: Overflow, a: i64, bits: u64) -> Result<String, ErrorKind> {
+        overflow.shl(a, bits).map(|val| val.to_string()).map_err(|e| e.kind().clone())
+    }
+
+    #[test]
+    fn shl_that_keeps_every_bit_is_the_same_under_every_policy() {
+        for overflow in [Overflow::Promote, Overflow::Error, Overflow::Wrap, Overflow::Saturate] {
+            assert_eq!(shl(overflow, 1, 62), Ok(String::from("4611686018427387904")));
+            assert_eq!(shl(overflow, -1, 63), Ok(i64::MIN.to_string()));
+            assert_eq!(shl(overflow, 0, 1000), Ok(String::from("0")));
+        }
+    }
+
+    #[test]
+    fn shl_promotes_lost_bits() {
+        assert_eq!(shl(Overflow::Promote, 3, 62), Ok(String::from("13835058055282163712")));
+        assert_eq!(shl(Overflow::Promote, -3, 64), Ok(String::from("-55340232221128654848")));
+        assert_eq!(shl(Overflow::Promote, 1, MAX_POW_BITS + 1), Err(ErrorKind::Overflow));
+    }
+
+    #[test]
+    fn powers_too_big_to_work_out_quickly_are_an_error() {
+        // 3 is taken to have 2 bits, so this would be a little over a million of them
+        let pow = |base: i64, exp| Overflow::Promote.pow(base, exp).map_err(|e| e.kind().clone());
+        assert_eq!(pow(3, MAX_POW_BITS / 2 + 1), Err(ErrorKind::Overflow));
+        assert_eq!(pow(i64::MAX, MAX_POW_BITS), Err(ErrorKind::Overflow));
+        assert_eq!(pow(-1, u64::MAX).unwrap().to_string(), "-1");
+        let big = BigInt::from(i128::MAX);
+        assert_eq!(Overflow::Promote.pow_big(&big, MAX_POW_BITS / 127 + 1).unwrap_err().kind(), &ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn shl_errors_on_lost_bits() {
+        assert_eq!(shl(Overflow::Error, 3, 62), Err(ErrorKind::Overflow));
+        assert_eq!(shl(Overflow::Error, 1, 64), Err(ErrorKind::Overflow));
+        assert_eq!(shl(Overflow::Error, i64::MIN, 1), Err(ErrorKind::Overflow));
+    }
+
+    #[test]
+    fn shl_wraps_lost_bits() {
+        assert_eq!(shl(Overflow::Wrap, 3, 62), Ok(String::from("-4611686018427387904")));
+        assert_eq!(shl(Overflow::Wrap, i64::MIN, 1), Ok(String::from("0")));
+        assert_eq!(shl(Overflow::Wrap, 5, 64), Ok(String::from("0")));
+    }
+
+    #[test]
+    fn shl_saturates_lost_bits() {
+        assert_eq!(shl(Overflow::Saturate, 3, 62), Ok(i64::MAX.to_string()));
+        assert_eq!(shl(Overflow::Saturate, -3, 62), Ok(i64::MIN.to_string()));
+        assert_eq!(shl(Overflow::Saturate, -1, 100), Ok(i64::MIN.to_string()));
+    }
+}
@@ -0,0 +1,982 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+
+use std::rc::Rc;
+
+use crate::{BigInt, BuiltIns, Construct, ErrorKind, Expression, Line, MyLangError, Pattern, Program, Span, StructType, Value, Variable};
+use crate::lexer::{tokenise, unescape, Token, TokenKind};
+use crate::user_function::{Signature, UserFunction};
+
+// how deeply brackets, lists, indexes and blocks can be nested inside each other. each level takes stack
+// to parse and optimise, up to a few tens of KB in a debug build for a bracketed built in, so a file
+// nested more deeply is a parse error rather than something that overflows the 2MB threads rust starts
+// by default
+const MAX_NESTING: usize = 50;
+
+// a recursive descent parser over the tokens of a single file
+pub struct Parser<'a, 'u> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    user_fns: &'u mut HashMap<&'a str, UserFunction<'a>>,
+    // the arguments each function declared in the file takes, so functions can be called before they are
+    // declared, and from inside themselves
+    signatures: HashMap<&'a str, Signature>,
+    // the functions defined inside the function being parsed that can be called at this point
+    nested: Vec<(&'a str, Signature)>,
+    // how many loops the current line is inside of, within the current function
+    loops: usize,
+    // how many blocks the current line is inside of
+    blocks: usize,
+    // how many brackets, lists, indexes and blocks the current token is inside of
+    nesting: usize,
+    in_function: bool,
+}
+
+impl<'a, 'u> Parser<'a, 'u> {
+    pub fn new(text: &'a str, file: u32, user_fns: &'u mut HashMap<&'a str, UserFunction<'a>>) -> Result<Parser<'a, 'u>, MyLangError> {
+        let tokens = tokenise(text, file)?;
+        Ok(Parser {
+            signatures: signatures(&tokens),
+            tokens,
+            position: 0,
+            user_fns,
+            nested: Vec::new(),
+            loops: 0,
+            blocks: 0,
+            nesting: 0,
+            in_function: false,
+        })
+    }
+
+    // the whole file, which must not have any unmatched `}`
+    pub fn parse_program(&mut self) -> Result<Program<'a>, MyLangError> {
+        let program = self.parse_lines()?;
+        match self.peek().kind {
+            TokenKind::Eof => Ok(program),
+            _ => Err(expected("a line", self.peek())),
+        }
+    }
+
+    // `{ LINES }`. `opened_by` is whatever the block belongs to, which is blamed if it's never closed
+    fn parse_block(&mut self, opened_by: Span) -> Result<Program<'a>, MyLangError> {
+        let open = self.expect(TokenKind::OpenBrace, "\"{\"")?;
+        let nested = self.nested.len();
+        self.blocks += 1;
+        let program = self.nest(open.span, Self::parse_lines);
+        self.blocks -= 1;
+        self.nested.truncate(nested);
+        let program = program?;
+        match self.peek().kind {
+            TokenKind::CloseBrace => {
+                self.advance();
+                Ok(program)
+            }
+            _ => Err(ErrorKind::UnclosedBlock.at(opened_by)),
+        }
+    }
+
+    // the block of a loop, where break and continue can be used
+    fn parse_loop_block(&mut self, opened_by: Span) -> Result<Program<'a>, MyLangError> {
+        self.loops += 1;
+        let program = self.parse_block(opened_by);
+        self.loops -= 1;
+        program
+    }
+
+    // lines up until the end of the file or the `}` closing the current block. lines are separated by
+    // new lines or `;`
+    fn parse_lines(&mut self) -> Result<Program<'a>, MyLangError> {
+        let mut program = Vec::new();
+        loop {
+            match self.peek().kind {
+                TokenKind::Newline | TokenKind::Semicolon => {
+                    self.advance();
+                }
+                TokenKind::CloseBrace | TokenKind::Eof => return Ok(Program::new(program)),
+                _ => {
+                    if let Some(line) = self.parse_line()? {
+                        program.push(line);
+                    }
+                }
+            }
+        }
+    }
+
+    // function declarations are added to the user functions rather than giving a line, unless they're
+    // inside another function
+    fn parse_line(&mut self) -> Result<Option<Line<'a>>, MyLangError> {
+        let start = self.peek().span;
+        let line = match (self.peek().kind, self.peek_next().kind) {
+            (TokenKind::Func, _) => return self.parse_function(),
+            (TokenKind::Struct, _) => {
+                self.advance();
+                self.parse_type("a struct name", None)?;
+                self.end_of_line()?;
+                return Ok(None);
+            }
+            (TokenKind::Enum, _) => {
+                self.parse_enum()?;
+                return Ok(None);
+            }
+            // `import "PATH"`. the file has already been read in and parsed by the time this one is, see
+            // `load_imports`
+            (TokenKind::Import, _) => {
+                let token = self.advance();
+                if self.blocks > 0 {
+                    return Err(ErrorKind::NotTopLevel(String::from("import")).at(token.span));
+                }
+                match self.advance() {
+                    Token { kind: TokenKind::Str(_), .. } => (),
+                    found => return Err(expected("the path of the file to import", found)),
+                }
+                self.end_of_line()?;
+                return Ok(None);
+            }
+            (TokenKind::If, _) | (TokenKind::While, _) | (TokenKind::For, _) | (TokenKind::Match, _) => {
+                let (construct, span) = self.parse_construct()?;
+                return Ok(Some(Line::Construct(construct, span)));
+            }
+            (TokenKind::Break, _) | (TokenKind::Continue, _) => {
+                let token = self.advance();
+                if self.loops == 0 {
+                    let keyword = if token.kind == TokenKind::Break { "break" } else { "continue" };
+                    return Err(ErrorKind::OutsideLoop(String::from(keyword)).at(token.span));
+                }
+                if token.kind == TokenKind::Break {
+                    Line::Break(token.span)
+                } else {
+                    Line::Continue(token.span)
+                }
+            }
+            // `return` or `return EXPRESSION`
+            (TokenKind::Return, _) => {
+                let token = self.advance();
+                if !self.in_function {
+                    return Err(ErrorKind::OutsideFunction(String::from("return")).at(token.span));
+                }
+                let exp = match self.peek().kind {
+                    TokenKind::Newline | TokenKind::Semicolon | TokenKind::CloseBrace | TokenKind::Eof => None,
+                    _ => Some(self.parse_expression()?),
+                };
+                Line::Return(exp, start.to(self.previous().span))
+            }
+            // `let var: EXPRESSION`
+            (TokenKind::Let, _) => {
+                self.advance();
+                let var = self.declare_ident("a variable name")?;
+                self.expect(TokenKind::Colon, "\":\"")?;
+                let exp = self.parse_expression()?;
+                Line::Let(Variable::new(var), exp, start.to(self.previous().span))
+            }
+            // an assignment will be of the form `var: EXPRESSION`
+            (TokenKind::Ident(var), TokenKind::Colon) => {
+                not_built_in(var, start)?;
+                self.advance();
+                self.advance();
+                let exp = self.parse_expression()?;
+                Line::Assignment(Variable::new(var), exp, start.to(self.previous().span))
+            }
+            // `LIST[INDEX]: EXPRESSION` and `STRUCT.FIELD: EXPRESSION` can only be told apart from an expression
+            // once the index or field has been parsed
+            _ => {
+                let exp = self.parse_expression()?;
+                match (exp, self.peek().kind) {
+                    (Expression::Index(list, index, _), TokenKind::Colon) => {
+                        self.advance();
+                        let value = self.parse_expression()?;
+                        Line::IndexAssignment(*list, *index, value, start.to(self.previous().span))
+                    }
+                    (Expression::Field(record, field, _), TokenKind::Colon) => {
+                        self.advance();
+                        let value = self.parse_expression()?;
+                        Line::FieldAssignment(*record, field, value, start.to(self.previous().span))
+                    }
+                    (exp, _) => Line::Expression(exp, start.to(self.previous().span)),
+                }
+            }
+        };
+        self.end_of_line()?;
+        Ok(Some(line))
+    }
+
+    // function declaration of form `func func_name a r g s { LINES }`, with no arguments at all or ending in
+    // `...rest` to take any number of them. one inside another function gives a line, as it can only be
+    // called from the point it's defined until the end of the block it's in
+    fn parse_function(&mut self) -> Result<Option<Line<'a>>, MyLangError> {
+        let start = self.advance().span;
+        let name = self.declare_ident("a function name")?;
+        let nested = self.in_function;
+        let (func, span) = self.parse_definition(name, start, nested)?;
+        if nested {
+            return Ok(Some(Line::Function(Box::from(func), span)));
+        }
+        self.define(func)?;
+        Ok(None)
+    }
+
+    // add a function declared outside of any other. its name can't already be taken by one from this or
+    // any other file
+    fn define(&mut self, func: UserFunction<'a>) -> Result<(), MyLangError> {
+        if let Some(first) = self.user_fns.get(func.name) {
+            let name = String::from(func.name);
+            return Err(ErrorKind::DuplicateFunction { name, first: first.span }.at(func.span));
+        }
+        self.user_fns.insert(func.name, func);
+        Ok(())
+    }
+
+    // `enum NAME { VARIANTS }`, with each variant on its own line written like a struct, as `VARIANT FIELDS`
+    fn parse_enum(&mut self) -> Result<(), MyLangError> {
+        let start = self.advance().span;
+        let name = self.expect_ident("an enum name")?;
+        let span = start.to(self.previous().span);
+        self.expect(TokenKind::OpenBrace, "\"{\"")?;
+        loop {
+            match self.peek().kind {
+                TokenKind::Newline | TokenKind::Semicolon => {
+                    self.advance();
+                }
+                TokenKind::CloseBrace => {
+                    self.advance();
+                    return Ok(());
+                }
+                TokenKind::Eof => return Err(ErrorKind::UnclosedBlock.at(span)),
+                _ => {
+                    self.parse_type("a variant name", Some(name))?;
+                    self.end_of_line()?;
+                }
+            }
+        }
+    }
+
+    // the `NAME FIELDS` of a struct, or of a variant of the enum `enum_name`. this declares a type along with
+    // a function of the same name that takes a value for each field and gives back a new value of the type.
+    // like any function, it can be used before it's declared
+    fn parse_type(&mut self, description: &str, enum_name: Option<&'a str>) -> Result<(), MyLangError> {
+        let start = self.peek().span;
+        let name = self.declare_ident(description)?;
+        let mut fields = Vec::new();
+        while let TokenKind::Ident(field) = self.peek().kind {
+            let token = self.advance();
+            if fields.contains(&field) {
+                return Err(expected("a field name that hasn't been used", token));
+            }
+            fields.push(field);
+        }
+        let span = start.to(self.previous().span);
+        let kind = Rc::new(StructType { name, fields: fields.clone(), enum_name });
+        let values = fields.iter()
+            .map(|&field| Expression::Variable(Variable::new(field), span))
+            .collect();
+        let code = vec![Line::Return(Some(Expression::Struct(Rc::clone(&kind), values, span)), span)];
+        self.define(UserFunction {
+            name,
+            code: Program::new(code),
+            args: fields.clone(),
+            rest: None,
+            names: fields,
+            kind: Some(kind),
+            optimised: OnceCell::new(),
+            span,
+        })
+    }
+
+    // a function's arguments and code, following its name if it has one. a function defined inside another
+    // can call itself. the span returned covers the part before the code
+    fn parse_definition(&mut self, name: &'a str, start: Span, nested: bool) -> Result<(UserFunction<'a>, Span), MyLangError> {
+        let mut args = Vec::new();
+        while let TokenKind::Ident(arg) = self.peek().kind {
+            not_built_in(arg, self.advance().span)?;
+            args.push(arg);
+        }
+        let rest = match self.peek().kind {
+            TokenKind::Ellipsis => {
+                self.advance();
+                Some(self.declare_ident("a name for the rest of the arguments")?)
+            }
+            _ => None,
+        };
+        let span = start.to(self.previous().span);
+        if nested {
+            self.nested.push((name, Signature { args: args.len(), rest: rest.is_some() }));
+        }
+
+        // loops outside of the function can't be broken out of from inside it
+        let outer = (self.loops, self.in_function);
+        self.loops = 0;
+        self.in_function = true;
+        let body = self.position;
+        let code = self.parse_block(span);
+        self.loops = outer.0;
+        self.in_function = outer.1;
+
+        let code = code?;
+        let mut names = self.tokens[body..self.position].iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::Ident(name) => Some(name),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        let func = UserFunction {
+            name,
+            code,
+            args,
+            rest,
+            names,
+            kind: None,
+            optimised: OnceCell::new(),
+            span,
+        };
+        Ok((func, span))
+    }
+
+    // one of `if EXPRESSION {`, `while EXPRESSION {`, `for VAR_NAME ARGUMENT ARGUMENT {` or
+    // `for VAR_NAME in EXPRESSION {`, followed by
+    // the rest of the block. an if can be followed by any number of `} elif EXPRESSION {` blocks and then
+    // a `} else {` block. `match EXPRESSION { ARMS }` has a block for each arm, see `parse_match`. the span
+    // returned covers the part before the first block
+    fn parse_construct(&mut self) -> Result<(Construct<'a>, Span), MyLangError> {
+        let keyword = self.advance();
+        let construct = match keyword.kind {
+            TokenKind::If => {
+                let expression = self.parse_expression()?;
+                let span = keyword.span.to(self.previous().span);
+                let mut arms = vec![(expression, self.parse_block(span)?)];
+                while self.peek().kind == TokenKind::Elif {
+                    let elif = self.advance();
+                    let expression = self.parse_expression()?;
+                    let elif_span = elif.span.to(self.previous().span);
+                    arms.push((expression, self.parse_block(elif_span)?));
+                }
+                let otherwise = match self.peek().kind {
+                    TokenKind::Else => {
+                        let span = self.advance().span;
+                        Some(self.parse_block(span)?)
+                    }
+                    _ => None,
+                };
+                (Construct::If(arms, otherwise, span), span)
+            }
+            TokenKind::While => {
+                let expression = self.parse_expression()?;
+                let span = keyword.span.to(self.previous().span);
+                (Construct::While(expression, self.parse_loop_block(span)?, span), span)
+            }
+            TokenKind::Match => {
+                let expression = self.parse_expression()?;
+                let span = keyword.span.to(self.previous().span);
+                (self.parse_match(expression, span)?, span)
+            }
+            _ => {
+                let iterating = Variable::new(self.declare_ident("a loop variable name")?);
+                if self.peek().kind == TokenKind::In {
+                    self.advance();
+                    let list = self.parse_expression()?;
+                    let span = keyword.span.to(self.previous().span);
+                    return Ok((Construct::ForEach(iterating, list, self.parse_loop_block(span)?, span), span));
+                }
+                let start = self.parse_argument()?;
+                let end = self.parse_argument()?;
+                let span = keyword.span.to(self.previous().span);
+                (Construct::For(iterating, start, end, self.parse_loop_block(span)?, span), span)
+            }
+        };
+        Ok(construct)
+    }
+
+    // the arms of a match, each a pattern followed by a block, up to the `}` closing them all. the last arm
+    // can be `else { LINES }`, which is run if no pattern matches. a pattern is a literal, a range of
+    // literals `LOW..HIGH` that covers `LOW` up to but not including `HIGH`, or the name of a struct or
+    // enum variant followed by a variable for each of its fields
+    fn parse_match(&mut self, expression: Expression<'a>, span: Span) -> Result<Construct<'a>, MyLangError> {
+        self.expect(TokenKind::OpenBrace, "\"{\"")?;
+        let mut arms = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let token = self.peek();
+            let pattern = match token.kind {
+                TokenKind::Newline | TokenKind::Semicolon => {
+                    self.advance();
+                    continue;
+                }
+                TokenKind::CloseBrace if !arms.is_empty() || otherwise.is_some() => {
+                    self.advance();
+                    return Ok(Construct::Match(expression, arms, otherwise, span));
+                }
+                TokenKind::Eof => return Err(ErrorKind::UnclosedBlock.at(span)),
+                _ if otherwise.is_some() => return Err(expected("\"}\" after the else arm", token)),
+                TokenKind::Else => {
+                    self.advance();
+                    otherwise = Some(self.parse_block(token.span)?);
+                    continue;
+                }
+                TokenKind::Ident(name) => {
+                    self.advance();
+                    let mut fields = Vec::new();
+                    while let TokenKind::Ident(field) = self.peek().kind {
+                        not_built_in(field, self.advance().span)?;
+                        fields.push(Variable::new(field));
+                    }
+                    Pattern::Named(name, fields, token.span.to(self.previous().span))
+                }
+                TokenKind::Int(_) | TokenKind::Float(_) | TokenKind::Str(_) | TokenKind::Bool(_) => {
+                    let low = self.parse_literal()?;
+                    if self.peek().kind == TokenKind::DotDot {
+                        self.advance();
+                        Pattern::Range(low, self.parse_literal()?)
+                    } else {
+                        Pattern::Literal(low)
+                    }
+                }
+                _ => return Err(expected("a pattern", token)),
+            };
+            let pattern_span = token.span.to(self.previous().span);
+            arms.push((pattern, self.parse_block(pattern_span)?));
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Value<'a>, MyLangError> {
+        let token = self.peek();
+        match self.parse_atom()? {
+            Expression::Literal(val, _) => Ok(val),
+            _ => Err(expected("a literal", token)),
+        }
+    }
+
+    // calls joined by infix operators, like `n % 2 == 0`, which give the same built ins as `== (% n 2) 0`.
+    // an operator only starts a call when it's at the start of an expression, anywhere else it's infix. the
+    // arguments of a call end at an infix operator, so `f x + 1` adds 1 to whatever `f x` gives back. that
+    // makes `print a + b` an error, as `print` gives back nothing to add to
+    fn parse_expression(&mut self) -> Result<Expression<'a>, MyLangError> {
+        self.parse_infix(0)
+    }
+
+    // operators that bind looser than `min_precedence` are left for the caller. operators with the same
+    // precedence are worked out from left to right, and a run of the same operator that can take more than
+    // two arguments gives a single built in, so `a < b < c` is chained like `< a b c`. `==` and `!=` can't be
+    // chained, as `a == b == c` reads like it checks all three are equal
+    fn parse_infix(&mut self, min_precedence: u8) -> Result<Expression<'a>, MyLangError> {
+        let mut lhs = self.parse_call()?;
+        while let Some((op, precedence)) = infix_operator(self.peek()) {
+            if precedence < min_precedence {
+                break;
+            }
+            if let Expression::BuiltInFunction(built_in, span) = &lhs {
+                if !built_in.gives_value() {
+                    let name = String::from(built_in.name());
+                    return Err(ErrorKind::NoValueOperand { name, operator: String::from(op) }.at(span.to(self.peek().span)));
+                }
+            }
+            self.advance();
+            let mut operands = vec![lhs, self.parse_infix(precedence + 1)?];
+            if matches!(op, "==" | "!=") {
+                if let Some((next @ ("==" | "!="), _)) = infix_operator(self.peek()) {
+                    return Err(ErrorKind::ChainedEquality(String::from(next)).at(self.peek().span));
+                }
+            }
+            while infix_operator(self.peek()).is_some_and(|(next, _)| next == op) && !matches!(op, "/" | "%" | "<<" | ">>" | "==" | "!=") {
+                self.advance();
+                operands.push(self.parse_infix(precedence + 1)?);
+            }
+            let span = operands[0].span().to(operands[operands.len() - 1].span());
+            let built_in = BuiltIns::from_name(op, operands).map_err(|e| e.at(span))?;
+            lhs = Expression::BuiltInFunction(Box::from(built_in), span);
+        }
+        Ok(lhs)
+    }
+
+    // a call to a built in or user function along with all of its arguments, or else a single argument.
+    // a user function name only counts as a call if it's followed by arguments, the function takes none at
+    // all, not even a rest argument, or it's alone in brackets, otherwise it's the function as a value. a
+    // variable, index or field followed by arguments calls the function value it holds, unless the first is
+    // a negative number, as `n -1` is far more likely meant to take 1 away
+    fn parse_call(&mut self) -> Result<Expression<'a>, MyLangError> {
+        let token = self.peek();
+        match token.kind {
+            TokenKind::Operator(name) | TokenKind::Ident(name) if BuiltIns::is_built_in(name) => {
+                self.advance();
+                let args = self.parse_arguments()?;
+                let span = token.span.to(self.previous().span);
+                let built_in = BuiltIns::from_name(name, args).map_err(|e| e.at(span))?;
+                Ok(Expression::BuiltInFunction(Box::from(built_in), span))
+            }
+            TokenKind::Ident(name) if self.is_call(name, self.peek_next()) => {
+                self.advance();
+                let args = self.parse_arguments()?;
+                let span = token.span.to(self.previous().span);
+                self.signature(name).unwrap().check(name, args.len()).map_err(|e| e.at(span))?;
+                Ok(Expression::UserFunction(name, args, span))
+            }
+            _ => {
+                let argument = self.parse_argument()?;
+                match argument {
+                    Expression::Variable(..) | Expression::Index(..) | Expression::Field(..) if self.starts_argument(self.peek()) => {
+                        if let TokenKind::Int(number) | TokenKind::Float(number) = self.peek().kind {
+                            if number.starts_with('-') {
+                                return Err(ErrorKind::NegativeArgument(String::from(number)).at(self.peek().span));
+                            }
+                        }
+                        let args = self.parse_arguments()?;
+                        let span = token.span.to(self.previous().span);
+                        Ok(Expression::Call(Box::from(argument), args, span))
+                    }
+                    argument => Ok(argument),
+                }
+            }
+        }
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Expression<'a>>, MyLangError> {
+        let mut args = Vec::new();
+        while self.starts_argument(self.peek()) {
+            args.push(self.parse_argument()?);
+        }
+        Ok(args)
+    }
+
+    // an infix operator ends the arguments of a call
+    fn starts_argument(&self, token: Token<'a>) -> bool {
+        matches!(token.kind, TokenKind::Int(_) | TokenKind::Float(_) | TokenKind::Str(_) | TokenKind::Bool(_)
+            | TokenKind::Ident(_) | TokenKind::OpenBracket | TokenKind::OpenSquare | TokenKind::Func | TokenKind::At)
+            && infix_operator(token).is_none()
+    }
+
+    // an atom followed by any number of indexes and fields. an index has to be written right after what it's
+    // indexing as `xs [i]` is two arguments, a list and then another list
+    fn parse_argument(&mut self) -> Result<Expression<'a>, MyLangError> {
+        let mut argument = self.parse_atom()?;
+        loop {
+            match self.peek().kind {
+                TokenKind::OpenSquare if self.touching_previous() => {
+                    let open = self.advance();
+                    let index = self.nest(open.span, Self::parse_expression)?;
+                    self.expect(TokenKind::CloseSquare, "\"]\"")?;
+                    let span = argument.span().to(self.previous().span);
+                    argument = Expression::Index(Box::from(argument), Box::from(index), span);
+                }
+                TokenKind::Dot => {
+                    self.advance();
+                    let field = self.expect_ident("a field name")?;
+                    let span = argument.span().to(self.previous().span);
+                    argument = Expression::Field(Box::from(argument), field, span);
+                }
+                _ => return Ok(argument),
+            }
+        }
+    }
+
+    // a literal, a variable, a built in or user function called with no arguments, a function value, a list or a
+    // bracketed expression
+    fn parse_atom(&mut self) -> Result<Expression<'a>, MyLangError> {
+        let token = self.advance();
+        match token.kind {
+            // an int literal too big for an `Int` is a big int
+            TokenKind::Int(literal) => match literal.parse() {
+                Ok(val) => Ok(Expression::Literal(Value::Int(val), token.span)),
+                Err(_) => BigInt::parse(literal)
+                    .map(|val| Expression::Literal(Value::big(val), token.span))
+                    .ok_or_else(|| ErrorKind::Parse(String::from(literal)).at(token.span)),
+            },
+            TokenKind::Float(literal) => literal.parse()
+                .map(|val| Expression::Literal(Value::Float(val), token.span))
+                .map_err(|_| ErrorKind::Parse(String::from(literal)).at(token.span)),
+            TokenKind::Str(literal) => Ok(Expression::Literal(Value::Str(unescape(literal).into()), token.span)),
+            TokenKind::Bool(b) => Ok(Expression::Literal(Value::Bool(b), token.span)),
+            TokenKind::Operator(name) | TokenKind::Ident(name) if BuiltIns::is_built_in(name) => {
+                let built_in = BuiltIns::from_name(name, Vec::new()).map_err(|e| e.at(token.span))?;
+                Ok(Expression::BuiltInFunction(Box::from(built_in), token.span))
+            }
+            TokenKind::Ident(name) if self.signature(name).is_some_and(Signature::takes_nothing) => {
+                Ok(Expression::UserFunction(name, Vec::new(), token.span))
+            }
+            TokenKind::Ident(name) if self.signature(name).is_some() => Ok(Expression::FunctionValue(name, token.span)),
+            // `@name`, the function as a value even if it can be called without any arguments
+            TokenKind::At => {
+                let name = self.expect_ident("a function name")?;
+                let span = token.span.to(self.previous().span);
+                match self.signature(name) {
+                    Some(_) => Ok(Expression::FunctionValue(name, span)),
+                    None => Err(ErrorKind::UndefinedFunction(String::from(name)).at(span)),
+                }
+            }
+            // `func a r g s { LINES }`
+            TokenKind::Func => {
+                let (func, span) = self.parse_definition("lambda", token.span, false)?;
+                Ok(Expression::Lambda(Box::from(func), span))
+            }
+            TokenKind::Ident(name) => Ok(Expression::Variable(Variable::new(name), token.span)),
+            // `(f)` on its own calls a user function with no arguments, even one that could be given some
+            TokenKind::OpenBracket => self.nest(token.span, |parser| {
+                if let (TokenKind::Ident(name), TokenKind::CloseBracket) = (parser.peek().kind, parser.peek_next().kind) {
+                    if let Some(signature) = parser.signature(name) {
+                        let span = parser.advance().span;
+                        parser.advance();
+                        signature.check(name, 0).map_err(|e| e.at(span))?;
+                        return Ok(Expression::UserFunction(name, Vec::new(), span));
+                    }
+                }
+                let expression = parser.parse_expression()?;
+                parser.expect(TokenKind::CloseBracket, "\")\"")?;
+                Ok(expression)
+            }),
+            // `[:]` or `[ARGUMENT: ARGUMENT ...]` for a map, else `[ARGUMENTS]` for a list
+            TokenKind::OpenSquare => self.nest(token.span, |parser| {
+                if parser.peek().kind == TokenKind::Colon {
+                    parser.advance();
+                    let end = parser.expect(TokenKind::CloseSquare, "\"]\"")?;
+                    return Ok(Expression::Map(Vec::new(), token.span.to(end.span)));
+                }
+                let items = parser.parse_arguments()?;
+                if items.len() == 1 && parser.peek().kind == TokenKind::Colon {
+                    let mut entries = Vec::new();
+                    let mut key = items.into_iter().next().unwrap();
+                    loop {
+                        parser.expect(TokenKind::Colon, "\":\"")?;
+                        entries.push((key, parser.parse_argument()?));
+                        if !parser.starts_argument(parser.peek()) {
+                            break;
+                        }
+                        key = parser.parse_argument()?;
+                    }
+                    let end = parser.expect(TokenKind::CloseSquare, "\"]\"")?;
+                    return Ok(Expression::Map(entries, token.span.to(end.span)));
+                }
+                let end = parser.expect(TokenKind::CloseSquare, "\"]\"")?;
+                Ok(Expression::List(items, token.span.to(end.span)))
+            }),
+            _ => Err(expected("an expression", token)),
+        }
+    }
+
+    // parses what's inside the bracket or brace at `open`, unless that would nest them too deeply
+    fn nest<T>(&mut self, open: Span, parse: impl FnOnce(&mut Self) -> Result<T, MyLangError>) -> Result<T, MyLangError> {
+        if self.nesting == MAX_NESTING {
+            return Err(ErrorKind::NestingLimit(MAX_NESTING).at(open));
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    // a line must be followed by a new line, a `;`, the `}` closing its block or the end of the file
+    fn end_of_line(&mut self) -> Result<(), MyLangError> {
+        match self.peek().kind {
+            TokenKind::Newline | TokenKind::Semicolon => {
+                self.advance();
+                Ok(())
+            }
+            TokenKind::CloseBrace | TokenKind::Eof => Ok(()),
+            _ => Err(expected("end of line", self.peek())),
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind<'a>, description: &str) -> Result<Token<'a>, MyLangError> {
+        let token = self.advance();
+        if token.kind == kind {
+            Ok(token)
+        } else {
+            Err(expected(description, token))
+        }
+    }
+
+    fn expect_ident(&mut self, description: &str) -> Result<&'a str, MyLangError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Ident(ident) => Ok(ident),
+            _ => Err(expected(description, token)),
+        }
+    }
+
+    // a name being given to a function, variable or argument
+    fn declare_ident(&mut self, description: &str) -> Result<&'a str, MyLangError> {
+        let name = self.expect_ident(description)?;
+        not_built_in(name, self.previous().span)?;
+        Ok(name)
+    }
+
+    // the arguments a user function takes, if there is one with this name. functions defined inside the
+    // current one hide those declared outside of any
+    fn signature(&self, name: &str) -> Option<Signature> {
+        self.nested.iter()
+            .rev()
+            .find(|(nested, _)| *nested == name)
+            .map(|(_, sig)| *sig)
+            .or_else(|| self.signatures.get(name).copied())
+            .or_else(|| self.user_fns.get(name).map(UserFunction::signature))
+    }
+
+    // whether a name followed by `next` is a call to a user function
+    fn is_call(&self, name: &str, next: Token<'a>) -> bool {
+        match self.signature(name) {
+            Some(sig) => sig.takes_nothing() || self.starts_argument(next),
+            None => false,
+        }
+    }
+
+    // whether the next token starts right where the previous one ended
+    fn touching_previous(&self) -> bool {
+        let (previous, next) = (self.previous().span, self.peek().span);
+        previous.line == next.line && previous.column + previous.len == next.column
+    }
+
+    fn peek(&self) -> Token<'a> {
+        self.tokens[self.position]
+    }
+
+    fn peek_next(&self) -> Token<'a> {
+        self.tokens[(self.position + 1).min(self.tokens.len() - 1)]
+    }
+
+    fn previous(&self) -> Token<'a> {
+        self.tokens[self.position.saturating_sub(1)]
+    }
+
+    // the last token is always the end of the file, which is never moved past
+    fn advance(&mut self) -> Token<'a> {
+        let token = self.peek();
+        if self.position < self.tokens.len() - 1 {
+            self.position += 1;
+        }
+        token
+    }
+}
+
+// looks through the tokens for each `func NAME ARGS {` outside of any other function ahead of parsing them
+fn signatures<'a>(tokens: &[Token<'a>]) -> HashMap<&'a str, Signature> {
+    let mut signatures = HashMap::new();
+    // how many blocks deep each token is, and how deep the bodies of the functions it's inside start
+    let mut depth = 0;
+    let mut bodies = Vec::new();
+    let mut in_declaration = false;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBrace => {
+                depth += 1;
+                if in_declaration {
+                    bodies.push(depth);
+                    in_declaration = false;
+                }
+            }
+            TokenKind::CloseBrace => {
+                if bodies.last() == Some(&depth) {
+                    bodies.pop();
+                }
+                depth -= 1;
+            }
+            _ => (),
+        }
+        // structs and enum variants are always visible everywhere, wherever they're declared
+        if token.kind == TokenKind::Struct {
+            if let Some(TokenKind::Ident(name)) = tokens.get(i + 1).map(|t| t.kind) {
+                signatures.insert(name, Signature { args: idents(&tokens[i + 2..]), rest: false });
+            }
+            continue;
+        }
+        if token.kind == TokenKind::Enum {
+            // the variants start after `enum NAME {`, one at the start of each line
+            let mut starts_line = true;
+            for (j, token) in tokens.iter().enumerate().skip(i + 3) {
+                match token.kind {
+                    TokenKind::CloseBrace | TokenKind::Eof => break,
+                    TokenKind::Newline | TokenKind::Semicolon => starts_line = true,
+                    TokenKind::Ident(name) if starts_line => {
+                        signatures.insert(name, Signature { args: idents(&tokens[j + 1..]), rest: false });
+                        starts_line = false;
+                    }
+                    _ => starts_line = false,
+                }
+            }
+            continue;
+        }
+        if token.kind != TokenKind::Func {
+            continue;
+        }
+        in_declaration = true;
+        // a function without a name is never at the start of a line
+        let starts_line = i == 0 || matches!(tokens[i - 1].kind, TokenKind::Newline | TokenKind::Semicolon
+            | TokenKind::OpenBrace | TokenKind::CloseBrace);
+        if !bodies.is_empty() || !starts_line {
+            continue;
+        }
+        if let Some(TokenKind::Ident(name)) = tokens.get(i + 1).map(|t| t.kind) {
+            let args = idents(&tokens[i + 2..]);
+            let rest = tokens.get(i + 2 + args).is_some_and(|t| t.kind == TokenKind::Ellipsis);
+            signatures.insert(name, Signature { args, rest });
+        }
+    }
+    signatures
+}
+
+// the infix operator a token is, if it is one, along with how tightly it binds, higher binding tighter
+fn infix_operator<'a>(token: Token<'a>) -> Option<(&'a str, u8)> {
+    let op = match token.kind {
+        TokenKind::Operator(op) | TokenKind::Ident(op) => op,
+        _ => return None,
+    };
+    let precedence = match op {
+        "or" => 1,
+        "and" => 2,
+        "==" | "!=" | "<" | ">" | "<=" | ">=" => 3,
+        "|" => 4,
+        "^" => 5,
+        "&" => 6,
+        "<<" | ">>" => 7,
+        "+" | "-" => 8,
+        "*" | "/" | "%" => 9,
+        _ => return None,
+    };
+    Some((op, precedence))
+}
+
+// how many names there are in a row at the start of `tokens`
+fn idents(tokens: &[Token]) -> usize {
+    tokens.iter()
+        .take_while(|t| matches!(t.kind, TokenKind::Ident(_)))
+        .count()
+}
+
+// a built in is always called in place of anything else with its name, so nothing else can be given one
+fn not_built_in(name: &str, span: Span) -> Result<(), MyLangError> {
+    if BuiltIns::is_built_in(name) {
+        return Err(ErrorKind::BuiltInName(String::from(name)).at(span));
+    }
+    Ok(())
+}
+
+fn expected(expected: &str, found: Token) -> MyLangError {
+    ErrorKind::Expected {
+        expected: String::from(expected),
+        found: found.kind.describe(),
+    }.at(found.span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::tests::run;
+
+    fn parse(text: &str) -> Result<Program<'_>, MyLangError> {
+        Program::parse(text, 0, &mut HashMap::new())
+    }
+
+    #[test]
+    fn lines_can_share_a_line_and_blocks_can_be_on_one() {
+        let globals = run("x: 0; y: 0\nif false { x: 1 } elif true { x: 2 } else { x: 3 }\nwhile < y 3 { y: + y 1 }").unwrap();
+        assert_eq!(globals, ["2", "3"]);
+    }
+
+    #[test]
+    fn space_and_brackets_dont_change_what_a_line_means() {
+        let globals = run("\tx :  + (1)   (2)\ny: (+ (* 2 (+ 1 2)) ((3)))\nz: [(1) [2 (3)]]").unwrap();
+        assert_eq!(globals, ["3", "9", "[1 [2 3]]"]);
+    }
+
+    #[test]
+    fn unfinished_or_unexpected_tokens_are_errors_where_they_are() {
+        let error = |text| parse(text).unwrap_err();
+        let at = |line, column, len| Some(Span { file: 0, line, column, len });
+        assert_eq!(error("x: )").kind(), &ErrorKind::Expected { expected: String::from("an expression"), found: String::from("\")\"") });
+        assert_eq!(error("x: )").span(), at(1, 3, 1));
+        assert_eq!(error("x: 1 }").kind(), &ErrorKind::Expected { expected: String::from("a line"), found: String::from("\"}\"") });
+        assert_eq!(error("x: [1 (2").span(), at(1, 8, 0));
+        assert_eq!(error("while true {\n    x: 1\n").kind(), &ErrorKind::UnclosedBlock);
+        assert_eq!(error("while true {\n    x: 1\n").span(), at(1, 0, 10));
+    }
+
+    #[test]
+    fn brackets_and_blocks_can_only_be_nested_so_deeply() {
+        let nested = |open: &str, inner: &str, close: &str, n| format!("{}{}{}", open.repeat(n), inner, close.repeat(n));
+        let globals = run(&format!("x: {}\ny: {}\nz: 0\n{}", nested("(+ 1 ", "0", ")", MAX_NESTING / 2),
+            nested("[", "", "]", MAX_NESTING), nested("if true {\n", "z: 1\n", "}\n", MAX_NESTING))).unwrap();
+        assert_eq!(globals, [(MAX_NESTING / 2).to_string(), nested("[", "", "]", MAX_NESTING), String::from("1")]);
+        assert!(parse(&format!("x: {}", nested("(+ 1 ", "0", ")", MAX_NESTING))).is_ok());
+        let error = parse(&format!("x: {}", nested("(", "1", ")", 300_000))).unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::NestingLimit(MAX_NESTING));
+        assert_eq!(error.span(), Some(Span { file: 0, line: 1, column: 3 + MAX_NESTING as u32, len: 1 }));
+        for text in [nested("[", "", "]", MAX_NESTING + 1), nested("xs[", "0", "]", MAX_NESTING + 1),
+            nested("while true {\n", "", "}\n", MAX_NESTING + 1), nested("y: func {\n", "", "}\n", MAX_NESTING + 1)] {
+            assert_eq!(parse(&text).unwrap_err().kind(), &ErrorKind::NestingLimit(MAX_NESTING), "{}", text);
+        }
+    }
+
+    #[test]
+    fn call_without_a_value_cant_be_an_infix_operand() {
+        let error = parse("print 1 + 2").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::NoValueOperand { name: String::from("print"), operator: String::from("+") });
+        assert!(parse("print (1 + 2)").is_ok());
+    }
+
+    #[test]
+    fn a_negative_number_after_a_variable_is_an_error() {
+        let error = parse("n: 5\nx: n -1").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::NegativeArgument(String::from("-1")));
+        assert_eq!(error.span(), Some(Span { file: 0, line: 2, column: 5, len: 2 }));
+        for text in ["xs: [5]\nx: xs[0] -1.5", "struct p a\nq: p 5\nx: q.a -1"] {
+            assert!(matches!(parse(text).unwrap_err().kind(), ErrorKind::NegativeArgument(_)), "{}", text);
+        }
+        assert_eq!(run("n: 5\na: n - 1\nb: n-1\nc: abs -5\nd: + n -1").unwrap(), ["5", "4", "4", "5", "4"]);
+        assert_eq!(run("func f a {\n    res: - a\n}\ng: @f\nx: f -1\ny: g (-1)").unwrap(), ["<func f>", "1", "1"]);
+    }
+
+    #[test]
+    fn equality_cant_be_chained_infix() {
+        let error = parse("x: 1 == 1 == true").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::ChainedEquality(String::from("==")));
+        assert_eq!(error.span(), Some(Span { file: 0, line: 1, column: 10, len: 2 }));
+        assert!(matches!(parse("x: 1 != 2 == true").unwrap_err().kind(), ErrorKind::ChainedEquality(_)));
+        assert_eq!(run("a: == 1 1 1\nb: (1 == 1) == true\nc: 1 < 2 < 3\nd: 1 + 1 == 2").unwrap(), ["true", "true", "true", "true"]);
+    }
+
+    #[test]
+    fn a_function_name_can_only_be_declared_once() {
+        let error = parse("func f x {\n}\n\nfunc f y {\n}").unwrap_err();
+        let first = Span { file: 0, line: 1, column: 0, len: 8 };
+        assert_eq!(error.kind(), &ErrorKind::DuplicateFunction { name: String::from("f"), first });
+        assert_eq!(error.span(), Some(Span { file: 0, line: 4, column: 0, len: 8 }));
+        assert!(parse("struct f a\nfunc f {\n}").is_err());
+    }
+
+    #[test]
+    fn nothing_can_be_given_the_name_of_a_built_in() {
+        let error = |text| parse(text).unwrap_err();
+        assert_eq!(error("max: 5\nprint max").kind(), &ErrorKind::BuiltInName(String::from("max")));
+        assert_eq!(error("x: 1\nmax: 5").span(), Some(Span { file: 0, line: 2, column: 0, len: 3 }));
+        assert_eq!(error("func len a {\n}").span(), Some(Span { file: 0, line: 1, column: 5, len: 3 }));
+        assert_eq!(error("func f a map {\n}").span(), Some(Span { file: 0, line: 1, column: 9, len: 3 }));
+        for text in ["let log: 0", "func f ...keys {\n}", "g: func a abs {\n}", "for range 1 3 {\n}",
+            "for min in [] {\n}", "struct zip a", "enum e {\n    filter a\n}", "match 1 {\n    p fold {\n    }\n}"] {
+            assert!(matches!(error(text).kind(), ErrorKind::BuiltInName(_)), "{}", text);
+        }
+        assert!(parse("struct p len\nx: p 1\nprint x.len").is_ok());
+    }
+
+    #[test]
+    fn a_function_name_alone_in_brackets_is_called() {
+        let functions = "func total ...nums {\n    res: len nums\n}\nfunc f a {\n}\n";
+        let globals = run(&format!("{}a: total\nb: (total)\nc: (total 1 2)\nd: ((total))", functions)).unwrap();
+        assert_eq!(globals, ["<func total>", "0", "2", "0"]);
+        let error = run(&format!("{}x: (f)", functions)).unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::Arity { name: String::from("f"), expected: 1, found: 0 });
+        assert_eq!(error.span(), Some(Span { file: 0, line: 6, column: 4, len: 1 }));
+    }
+
+    #[test]
+    fn only_a_function_taking_nothing_is_called_by_its_name() {
+        assert_eq!(run("func f {\n    res: 1\n}\nx: f").unwrap(), ["1"]);
+        assert_eq!(run("func f ...xs {\n}\nx: f").unwrap(), ["<func f>"]);
+        assert_eq!(run("func f a {\n}\nx: f").unwrap(), ["<func f>"]);
+        assert_eq!(run("func f a {\n    res: a\n}\nx: f 2").unwrap(), ["2"]);
+    }
+
+    #[test]
+    fn at_gives_a_function_as_a_value() {
+        assert_eq!(run("func f {\n    res: 1\n}\nx: @f\ny: call x").unwrap(), ["<func f>", "1"]);
+        assert_eq!(run("func f ...xs {\n    res: len xs\n}\nx: call @f").unwrap(), ["0"]);
+        let error = parse("x: @f").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::UndefinedFunction(String::from("f")));
+        assert_eq!(error.span(), Some(Span { file: 0, line: 1, column: 3, len: 2 }));
+    }
+}
@@ -1,135 +1,296 @@
-use core::slice::Iter;
 use std::collections::HashMap;
+use std::rc::Rc;
 
-use regex::Regex;
-
-use crate::lib::{Construct, DataStore, Expression};
-use crate::lib::user_function::UserFunction;
+use crate::{Construct, DataStore, Expression, MyLangError, Scope, Sources, Span, Value, Variable};
+use crate::parser::Parser;
+use crate::user_function::UserFunction;
 
 #[derive(Debug)]
 pub enum Line<'a> {
-    Assignment(&'a str, Expression<'a>),
-    Expression(Expression<'a>),
-    Construct(Construct<'a>),
+    Assignment(Variable<'a>, Expression<'a>, Span),
+    // `let VAR: EXPRESSION` always makes a new variable, hiding any other with the same name until the end of the block
+    Let(Variable<'a>, Expression<'a>, Span),
+    // `LIST[INDEX]: EXPRESSION`
+    IndexAssignment(Expression<'a>, Expression<'a>, Expression<'a>, Span),
+    // `STRUCT.FIELD: EXPRESSION`
+    FieldAssignment(Expression<'a>, &'a str, Expression<'a>, Span),
+    Expression(Expression<'a>, Span),
+    Construct(Construct<'a>, Span),
+    Break(Span),
+    Continue(Span),
+    // `return` on its own gives back whatever `res` holds
+    Return(Option<Expression<'a>>, Span),
+    // a function defined inside another one, which can only be called from inside that function
+    Function(Box<UserFunction<'a>>, Span),
+}
+
+// what a program should do after running, which lets break, continue and return leave their block early
+#[derive(Debug, Clone, PartialEq)]
+pub enum Flow<'a> {
+    Next,
+    Break,
+    Continue,
+    Return(Option<Value<'a>>),
 }
 
 #[derive(Debug)]
 pub struct Program<'a> {
     program: Vec<Line<'a>>,
+    // the first slot of the variables made in this program, which are forgotten when it ends
+    clear_from: Option<usize>,
 }
 
 impl<'a> Program<'a> {
-    pub fn from_lines(lines: &mut Iter<&'a str>, user_fns: &mut HashMap<&'a str, UserFunction<'a>>) -> Program<'a> {
-        let assignment_regex = Regex::new(r"^([a-z_]+): (.+)$").unwrap();
-        let fn_regex = Regex::new(r"^func ([a-z_]+) (.+) \{$").unwrap();
+    pub fn new(program: Vec<Line<'a>>) -> Program<'a> {
+        Program {
+            program,
+            clear_from: None,
+        }
+    }
+
+    // parse a whole file. `file` is the id its spans will refer to. function declarations are added to `user_fns`
+    // rather than becoming lines of the program
+    pub fn parse(text: &'a str, file: u32, user_fns: &mut HashMap<&'a str, UserFunction<'a>>) -> Result<Program<'a>, MyLangError> {
+        Parser::new(text, file, user_fns)?.parse_program()
+    }
 
+    // parse a program along with the files it imports, as given by `load_imports`. the top level code of an
+    // imported file runs before the code of the file importing it, and can make globals too
+    pub fn parse_files(sources: &'a Sources, files: &[u32], user_fns: &mut HashMap<&'a str, UserFunction<'a>>) -> Result<Program<'a>, MyLangError> {
         let mut program = Vec::new();
+        for &file in files {
+            program.extend(Program::parse(&sources.get(file).text, file, user_fns)?.program);
+        }
+        Ok(Program::new(program))
+    }
 
-        while let Some(&line) = lines.next() {
-            if line.len() == 0 {
-                continue;
+    // the variables assigned at the top level of the program, which become globals if it's the main program
+    pub fn globals(&self) -> Rc<[&'a str]> {
+        let mut globals = Vec::new();
+        for line in &self.program {
+            if let Line::Assignment(var, _, _) | Line::Let(var, _, _) = line {
+                if !globals.contains(&var.name) {
+                    globals.push(var.name);
+                }
             }
+        }
+        Rc::from(globals)
+    }
 
-            // program moves back to outer scope that called it
-            if line == "}" {
-                break;
-            }
+    pub fn start(&self) -> Result<(), MyLangError> {
+        self.start_with_args(Vec::new())
+    }
 
-            // an assignment will be of the form `var: EXPRESSION`
-            if let Some(captures) = assignment_regex.captures(line) {
-                let var = captures.get(1).unwrap().as_str();
-                let args = captures.get(2).unwrap().as_str();
-                let exp = Expression::parse(args, &user_fns).unwrap();
-                program.push(Line::Assignment(var, exp));
-            } 
-            // check if line matches any of the constucts - if/while/for
-            else if let Some(construct) = Construct::parse(line, lines, user_fns) {
-                program.push(Line::Construct(construct));
-            } 
-            // function declaration of form `func func_name (a r g s) {`
-            else if let Some(captures) = fn_regex.captures(line) {
-                let fn_name = captures.get(1).unwrap().as_str();
-                let code = get_sub_program(lines);
-                let code = Program::from_lines(&mut code.iter(), user_fns);
-                let args = captures.get(2).unwrap().as_str().split(" ").collect();
-                let u_func = UserFunction {
-                    code,
-                    args,
-                };
-                user_fns.insert(fn_name, u_func);
-                println!("added user func: \"{}\"", fn_name);
-            } 
-            // xpressions can be literals, built in funcs, previously defined user funcs or variables.
-            // non-matches are currently assumed to be var names
-            else if let Some(expression) = Expression::parse(line, &user_fns) {
-                program.push(Line::Expression(expression));
-            }
-            else {
-                panic!(format!("unexpected input : \"{}\"", line))
-            }
-        }
+    // run with arguments the script can read via `argc` and `arg N`
+    pub fn start_with_args(&self, args: Vec<String>) -> Result<(), MyLangError> {
+        self.start_with(DataStore::with_args(args))
+    }
 
-        Program {
-            program
-        }
+    // run with a data store the host has set up, say with an overflow policy
+    pub fn start_with(&self, mut data_store: DataStore<'a>) -> Result<(), MyLangError> {
+        self.run_with(&mut data_store).map(|_| ())
     }
 
-    pub fn start(&self) {
-        self.run_with(&mut DataStore::new());
+    pub fn run_with(&self, data_store: &mut DataStore<'a>) -> Result<Flow<'a>, MyLangError> {
+        let result = self.run_lines(data_store);
+        if let Some(slot) = self.clear_from {
+            data_store.clear_from(slot);
+        }
+        result
     }
 
-    pub fn run_with(&self, data_store: &mut DataStore<'a>) {
-        data_store.expand();
+    // stops early if a line breaks, continues or returns, handing that back to whatever is running this program
+    fn run_lines(&self, data_store: &mut DataStore<'a>) -> Result<Flow<'a>, MyLangError> {
         for line in self.program.iter() {
             match line {
-                Line::Assignment(var, exp) => {
-                    let val = exp.evaluate(data_store).unwrap();
-                    data_store.put(var, val);
+                Line::Assignment(var, exp, span) | Line::Let(var, exp, span) => {
+                    let val = exp.evaluate(data_store)?;
+                    data_store.put(*var, val).map_err(|e| e.at(*span))?;
+                }
+                Line::IndexAssignment(list, index, exp, span) => {
+                    let list = list.evaluate(data_store)?;
+                    let index = index.evaluate(data_store)?;
+                    let val = exp.evaluate(data_store)?;
+                    list.set_index(&index, val).map_err(|e| e.at(*span))?;
+                }
+                Line::FieldAssignment(record, field, exp, span) => {
+                    let record = record.evaluate(data_store)?;
+                    let val = exp.evaluate(data_store)?;
+                    record.set_field(field, val).map_err(|e| e.at(*span))?;
                 }
-                Line::Expression(exp) => {
-                    exp.evaluate(data_store);
+                Line::Expression(exp, _) => {
+                    exp.execute(data_store)?;
                 }
-                Line::Construct(cons) => {
-                    cons.apply(data_store);
+                Line::Construct(cons, _) => {
+                    let flow = cons.apply(data_store)?;
+                    if !matches!(flow, Flow::Next) {
+                        return Ok(flow);
+                    }
                 }
+                Line::Break(_) => return Ok(Flow::Break),
+                Line::Continue(_) => return Ok(Flow::Continue),
+                Line::Return(exp, _) => {
+                    let val = match exp {
+                        Some(exp) => Some(exp.evaluate(data_store)?),
+                        None => None,
+                    };
+                    return Ok(Flow::Return(val));
+                }
+                Line::Function(_, _) => (),
             }
         }
-        data_store.contract();
+        Ok(Flow::Next)
+    }
+
+    // give each variable its slot, and each user function call the function it calls. the variables first
+    // assigned in this program are forgotten at the end of it
+    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &mut Scope<'a>) -> Result<Program<'a>, MyLangError> {
+        scope.expand();
+        let program = self.optimise_lines(user_fns, scope);
+        let clear_from = scope.contract();
+        Ok(Program {
+            clear_from,
+            ..program?
+        })
     }
 
-    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>) -> Program<'a> {
+    fn optimise_lines(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &mut Scope<'a>) -> Result<Program<'a>, MyLangError> {
         let mut new_program = Vec::new();
         for line in &self.program {
             let fixed = match line {
-                Line::Assignment(var_name, exp) => Line::Assignment(var_name, exp.optimise(user_fns)),
-                Line::Expression(exp) => Line::Expression(exp.optimise(user_fns)),
-                Line::Construct(cons) => Line::Construct(cons.optimise(user_fns))
+                // the variable doesn't exist until after the expression has been worked out
+                Line::Assignment(var, exp, span) => {
+                    let exp = exp.optimise(user_fns, scope)?;
+                    Line::Assignment(scope.declare(*var), exp, *span)
+                }
+                Line::Let(var, exp, span) => {
+                    let exp = exp.optimise(user_fns, scope)?;
+                    Line::Let(scope.declare_new(*var), exp, *span)
+                }
+                Line::IndexAssignment(list, index, exp, span) => {
+                    Line::IndexAssignment(list.optimise(user_fns, scope)?, index.optimise(user_fns, scope)?, exp.optimise(user_fns, scope)?, *span)
+                }
+                Line::FieldAssignment(record, field, exp, span) => {
+                    Line::FieldAssignment(record.optimise(user_fns, scope)?, field, exp.optimise(user_fns, scope)?, *span)
+                }
+                Line::Expression(exp, span) => Line::Expression(exp.optimise(user_fns, scope)?, *span),
+                Line::Construct(cons, span) => Line::Construct(cons.optimise(user_fns, scope)?, *span),
+                Line::Break(span) => Line::Break(*span),
+                Line::Continue(span) => Line::Continue(*span),
+                Line::Return(Some(exp), span) => Line::Return(Some(exp.optimise(user_fns, scope)?), *span),
+                Line::Return(None, span) => Line::Return(None, *span),
+                // there's nothing left to do once it's been optimised
+                Line::Function(func, _) => {
+                    func.optimise_nested(user_fns, scope)?;
+                    continue;
+                }
             };
             new_program.push(fixed);
         }
-        Program {
-            program: new_program
-        }
+        Ok(Program::new(new_program))
     }
 }
 
-// get all following lines from the inner level of indentation (if/while/for/function code)
-pub fn get_sub_program<'a>(lines: &mut Iter<&'a str>) -> Vec<&'a str> {
-    let mut res: Vec<&'a str> = Vec::new();
-    let mut brackets = 1;
-
-    while let Some(line) = lines.next() {
-        if line.ends_with(&"{") {
-            brackets += 1;
-        } else if line.eq(&"}") {
-            brackets -= 1;
-            if brackets == 0 {
-                return res;
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ErrorKind, Overflow, Slot};
+
+    // the values of the main program's top level variables once `text` has run, or the error it couldn't be
+    // parsed or run for
+    pub(crate) fn run(text: &str) -> Result<Vec<String>, MyLangError> {
+        run_with_overflow(text, Overflow::default())
+    }
+
+    pub(crate) fn run_with_overflow(text: &str, overflow: Overflow) -> Result<Vec<String>, MyLangError> {
+        let mut user_fns = HashMap::new();
+        let program = Program::parse(text, 0, &mut user_fns)?;
+        let globals = program.globals();
+        user_fns.values().try_for_each(|func| func.optimise(&user_fns, &globals))?;
+        let program = program.optimise(&user_fns, &mut Scope::new(Rc::clone(&globals)))?;
+        let mut data_store = DataStore::new().with_overflow(overflow);
+        program.run_with(&mut data_store)?;
+        (0..globals.len())
+            .map(|slot| data_store.get(Variable { name: globals[slot], slot: Some(Slot::Global(slot)) }).map(|val| val.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn break_only_leaves_the_innermost_loop() {
+        let globals = run("
+            pairs: []
+            for i 0 3 {
+                j: 0
+                while true {
+                    if == j i {
+                        break
+                    }
+                    push pairs (+ (* 10 i) j)
+                    j: + j 1
+                }
             }
-        }
+        ").unwrap();
+        assert_eq!(globals[0], "[10 20 21]");
+    }
 
-        res.push(line);
+    #[test]
+    fn continue_skips_the_rest_of_a_for_or_while_loop() {
+        let globals = run("
+            odd: []
+            for i 0 6 {
+                if == 0 (% i 2) {
+                    continue
+                }
+                push odd i
+            }
+            n: 0
+            skipped: 0
+            while < n 5 {
+                n: + n 1
+                if == n 3 {
+                    continue
+                }
+                skipped: + skipped n
+            }
+        ").unwrap();
+        assert_eq!(globals, ["[1 3 5]", "5", "12"]);
     }
 
-    panic!("unclosed pair of squiggly brackets");
-}
\ No newline at end of file
+    #[test]
+    fn return_leaves_a_function_from_inside_loops() {
+        let globals = run("
+            func first_over limit xs {
+                for x in xs {
+                    while true {
+                        if > x limit {
+                            return x
+                        }
+                        break
+                    }
+                }
+                return -1
+            }
+            a: first_over 2 [1 5 3]
+            b: first_over 9 [1 5 3]
+        ").unwrap();
+        assert_eq!(globals, ["5", "-1"]);
+    }
+
+    #[test]
+    fn return_outside_a_function_is_an_error() {
+        let error = run("x: 1\nreturn x").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::OutsideFunction(String::from("return")));
+        assert_eq!(error.span(), Some(Span { file: 0, line: 2, column: 0, len: 6 }));
+        assert!(run("for i 0 3 {\n    return\n}").is_err());
+    }
+
+    #[test]
+    fn break_and_continue_outside_a_loop_are_errors() {
+        let error = run("func stop {\n    break\n}\nfor i 0 3 {\n    stop\n}").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::OutsideLoop(String::from("break")));
+        assert_eq!(error.span(), Some(Span { file: 0, line: 2, column: 4, len: 5 }));
+        let error = run("for i 0 3 {\n    f: func {\n        continue\n    }\n}").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::OutsideLoop(String::from("continue")));
+        assert_eq!(run("continue").unwrap_err().kind(), &ErrorKind::OutsideLoop(String::from("continue")));
+    }
+}
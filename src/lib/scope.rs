@@ -0,0 +1,209 @@
+use std::rc::Rc;
+
+use crate::{ErrorKind, MyLangError, Span, UserFunction};
+
+// a variable name, along with where it lives once the program has been optimised
+#[derive(Debug, Clone, Copy)]
+pub struct Variable<'a> {
+    pub name: &'a str,
+    pub slot: Option<Slot>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Slot {
+    // a slot of the current frame
+    Local(usize),
+    // one of the variables assigned at the top level of the main program, which functions can read too
+    Global(usize),
+}
+
+// works out the slot of each variable while a program is optimised. a block's variables are forgotten
+// when it ends, and a variable is given the next free slot when it's first assigned, which is what lets
+// the data store add a variable by pushing it onto the end of the frame
+pub struct Scope<'a> {
+    // the variables that can be used at this point, innermost last
+    vars: Vec<Variable<'a>>,
+    // the functions defined inside this function that can be called at this point, along with the
+    // variables of this frame each one is given copies of
+    funcs: Vec<(&'a UserFunction<'a>, Vec<Variable<'a>>)>,
+    levels: Vec<Level>,
+    // how many slots of the frame are in use
+    slots: usize,
+    globals: Rc<[&'a str]>,
+    // the main program's top level variables are its globals, and it can't read them before they're assigned
+    main: bool,
+}
+
+// how much of the scope was in use when a block started
+struct Level {
+    vars: usize,
+    funcs: usize,
+    slots: usize,
+}
+
+impl<'a> Variable<'a> {
+    pub fn new(name: &'a str) -> Variable<'a> {
+        Variable {
+            name,
+            slot: None,
+        }
+    }
+}
+
+impl<'a> Scope<'a> {
+    // the scope of the main program. `globals` are the variables assigned at its top level, see `Program::globals`
+    pub fn new(globals: Rc<[&'a str]>) -> Scope<'a> {
+        Scope {
+            vars: Vec::new(),
+            funcs: Vec::new(),
+            levels: Vec::new(),
+            slots: 0,
+            globals,
+            main: true,
+        }
+    }
+
+    // a function's frame starts with `res`, then the copies of any variables it captured from the function
+    // it was defined in, then its arguments and then its rest argument
+    pub fn function(globals: Rc<[&'a str]>, captured: &[&'a str], args: &[&'a str], rest: Option<&'a str>) -> Scope<'a> {
+        let vars = std::iter::once("res")
+            .chain(captured.iter().copied())
+            .chain(args.iter().copied())
+            .chain(rest)
+            .enumerate()
+            .map(|(slot, name)| Variable { name, slot: Some(Slot::Local(slot)) })
+            .collect::<Vec<_>>();
+        Scope {
+            slots: vars.len(),
+            vars,
+            funcs: Vec::new(),
+            levels: Vec::new(),
+            globals,
+            main: false,
+        }
+    }
+
+    pub fn expand(&mut self) {
+        self.levels.push(Level {
+            vars: self.vars.len(),
+            funcs: self.funcs.len(),
+            slots: self.slots,
+        });
+    }
+
+    // gives back the first slot used by the level's variables, if it had any
+    pub fn contract(&mut self) -> Option<usize> {
+        let level = self.levels.pop().unwrap();
+        let declared = self.slots > level.slots;
+        self.vars.truncate(level.vars);
+        self.funcs.truncate(level.funcs);
+        self.slots = level.slots;
+        if declared { Some(level.slots) } else { None }
+    }
+
+    // the slot of a variable being read, which must already have been assigned. functions can read any
+    // global, whether or not it's been assigned yet is only known when they're called
+    pub fn resolve(&self, var: Variable<'a>, span: Span) -> Result<Variable<'a>, MyLangError> {
+        if let Some(found) = self.vars.iter().rev().find(|v| v.name == var.name) {
+            return Ok(Variable { slot: found.slot, ..var });
+        }
+        match self.globals.iter().position(|&g| g == var.name) {
+            Some(slot) if !self.main => Ok(Variable { slot: Some(Slot::Global(slot)), ..var }),
+            _ => Err(ErrorKind::UndefinedVariable(String::from(var.name)).at(span)),
+        }
+    }
+
+    // the slot of a variable being assigned, making a new one if it can't be seen yet. a function can't
+    // assign to a global, doing so makes a variable of its own instead
+    pub fn declare(&mut self, var: Variable<'a>) -> Variable<'a> {
+        match self.vars.iter().rev().find(|v| v.name == var.name) {
+            Some(found) => Variable { slot: found.slot, ..var },
+            None => self.declare_new(var),
+        }
+    }
+
+    // the slot of a variable declared with `let`, which hides any other variable with the same name until
+    // the end of the block
+    pub fn declare_new(&mut self, var: Variable<'a>) -> Variable<'a> {
+        let global = if self.main && self.levels.len() == 1 {
+            self.globals.iter().position(|&g| g == var.name)
+        } else {
+            None
+        };
+        let slot = match global {
+            Some(slot) => Slot::Global(slot),
+            None => {
+                self.slots += 1;
+                Slot::Local(self.slots - 1)
+            }
+        };
+        let var = Variable { slot: Some(slot), ..var };
+        self.vars.push(var);
+        var
+    }
+
+    // a function defined inside this one that can be called at this point, along with the variables to pass it
+    pub fn function_named(&self, name: &str) -> Option<(&'a UserFunction<'a>, &[Variable<'a>])> {
+        self.funcs.iter()
+            .rev()
+            .find(|(func, _)| func.name == name)
+            .map(|(func, captured)| (*func, captured.as_slice()))
+    }
+
+    // the scope of a function defined inside this one, which can be called from here on until the end of
+    // the block. it's given copies of the variables of this frame it might use, either itself or through
+    // the other functions defined in here that it calls. they're copied on every call, so it sees the
+    // values they have when it's called
+    pub fn nested(&mut self, func: &'a UserFunction<'a>) -> Scope<'a> {
+        let captured = self.captures(func);
+        self.funcs.push((func, captured.clone()));
+        self.inner(func, &captured)
+    }
+
+    // the scope of a function without a name made at this point, along with the variables of this frame it's
+    // given copies of. they're copied once, when the function value is made
+    pub fn lambda(&self, func: &'a UserFunction<'a>) -> (Scope<'a>, Vec<Variable<'a>>) {
+        let captured = self.captures(func);
+        (self.inner(func, &captured), captured)
+    }
+
+    // whether there's a variable with this name that can be read at this point, which hides any function
+    pub fn can_see(&self, name: &str) -> bool {
+        self.vars.iter().any(|v| v.name == name) || (!self.main && self.globals.contains(&name))
+    }
+
+    // the variables of this frame a function defined here might use, in the order of their slots. globals
+    // don't need copying as any function can read them
+    fn captures(&self, func: &UserFunction<'a>) -> Vec<Variable<'a>> {
+        let mut captured = func.names.iter()
+            .filter(|&&name| name != "res")
+            .filter_map(|&name| self.vars.iter().rev().find(|v| v.name == name).copied())
+            .filter(|var| matches!(var.slot, Some(Slot::Local(_))))
+            .collect::<Vec<_>>();
+        for (_, passed) in self.funcs.iter().filter(|(f, _)| func.names.contains(&f.name)) {
+            captured.extend_from_slice(passed);
+        }
+        captured.sort_by_key(|var| var.slot);
+        captured.dedup_by_key(|var| var.slot);
+        captured
+    }
+
+    fn inner(&self, func: &UserFunction<'a>, captured: &[Variable<'a>]) -> Scope<'a> {
+        let names = captured.iter().map(|var| var.name).collect::<Vec<_>>();
+        let mut scope = Scope::function(Rc::clone(&self.globals), &names, &func.args, func.rest);
+        // calls from inside it pass on its own copies of the variables
+        scope.funcs = self.funcs.iter()
+            .filter(|(f, _)| func.names.contains(&f.name))
+            .map(|(f, passed)| {
+                let passed = passed.iter()
+                    .map(|var| {
+                        let i = captured.iter().position(|c| c.slot == var.slot).unwrap();
+                        Variable { slot: Some(Slot::Local(1 + i)), ..*var }
+                    })
+                    .collect();
+                (*f, passed)
+            })
+            .collect();
+        scope
+    }
+}
@@ -0,0 +1,52 @@
+// every program file read in, so errors can point back at the text they came from
+#[derive(Debug, Default)]
+pub struct Sources {
+    files: Vec<Source>,
+}
+
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+// where something came from in the source. lines are counted from 1, columns are byte offsets into the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub file: u32,
+    pub line: u32,
+    pub column: u32,
+    pub len: u32,
+}
+
+impl Sources {
+    pub fn new() -> Sources {
+        Sources {
+            files: Vec::new(),
+        }
+    }
+
+    // returns the id spans use to refer to this file
+    pub fn add(&mut self, name: String, text: String) -> u32 {
+        self.files.push(Source { name, text });
+        (self.files.len() - 1) as u32
+    }
+
+    pub fn get(&self, file: u32) -> &Source {
+        &self.files[file as usize]
+    }
+}
+
+impl Span {
+    // a span covering from the start of this one to the end of `end`. if `end` is on a later
+    // line, the span stops at the end of this one instead
+    pub fn to(self, end: Span) -> Span {
+        if end.line != self.line || end.column < self.column {
+            return self;
+        }
+        Span {
+            len: end.column + end.len - self.column,
+            ..self
+        }
+    }
+}
@@ -1,36 +1,247 @@
-use crate::lib::{Program, Expression, DataStore};
+use crate::{ErrorKind, Program, Expression, DataStore, Flow, MyLangError, Scope, Slot, Span, StructType, Value, Variable};
+use std::cell::OnceCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
-// a function consists of its code and the names of the arguments you can pass it
+// a function consists of its code and the names of the arguments you can pass it. if it has a rest argument,
+// any arguments after the named ones are passed to it as a list. the optimised code is kept alongside the
+// parsed code, so calls from other functions can refer to this one before it's optimised
 #[derive(Debug)]
 pub struct UserFunction<'a> {
+    pub name: &'a str,
     pub code: Program<'a>,
     pub args: Vec<&'a str>,
+    pub rest: Option<&'a str>,
+    // every name used in the code, including inside functions defined in it. a function defined inside
+    // another is given copies of the variables these could refer to, see `Scope::nested`
+    pub names: Vec<&'a str>,
+    // the type it makes, if it's the function a struct or enum variant declaration gives
+    pub kind: Option<Rc<StructType<'a>>>,
+    pub optimised: OnceCell<Program<'a>>,
+    // where it's declared, up to the start of its code
+    pub span: Span,
+}
+
+// a function as a value, along with copies of the variables it captured, taken when the value was made
+#[derive(Debug)]
+pub struct Closure<'a> {
+    pub func: &'a UserFunction<'a>,
+    pub captured: Vec<Value<'a>>,
+}
+
+impl<'a> Closure<'a> {
+    pub fn call(&self, args: Vec<Value<'a>>, data_store: &mut DataStore<'a>, call: Span) -> Result<Value<'a>, MyLangError> {
+        self.func.call(&self.captured, args, data_store, call)
+    }
+}
+
+// how many arguments a function takes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signature {
+    pub args: usize,
+    pub rest: bool,
+}
+
+impl Signature {
+    // whether the function can only be called without arguments, so its name alone is a call to it
+    pub fn takes_nothing(self) -> bool {
+        self.args == 0 && !self.rest
+    }
+
+    pub fn check(self, name: &str, found: usize) -> Result<(), MyLangError> {
+        if self.rest && found < self.args {
+            return Err(ErrorKind::TooFewArguments {
+                name: String::from(name),
+                expected: self.args,
+                found,
+            }.into());
+        }
+        if !self.rest && found != self.args {
+            return Err(ErrorKind::Arity {
+                name: String::from(name),
+                expected: self.args,
+                found,
+            }.into());
+        }
+        Ok(())
+    }
 }
 
 impl<'a> UserFunction<'a> {
-    // 
-    pub fn apply(&self, vars: &Vec<Expression<'a>>, data_store: &mut DataStore<'a>) -> Option<i64> {
-        if self.args.len() != vars.len() {
-            panic!("please don't give me the wrong number of arguments")
+    pub fn signature(&self) -> Signature {
+        Signature {
+            args: self.args.len(),
+            rest: self.rest.is_some(),
+        }
+    }
+
+    // run the function with the given arguments, evaluated in the caller's data store. the result is the value it returns,
+    // or else whatever `res` holds at the end. `captured` are the caller's variables a function defined inside another is
+    // given copies of.
+    // `call` is where the function was called from, which is added to the trace of any error from inside the function
+    pub fn apply(&self, vars: &[Expression<'a>], captured: &[Variable<'a>], data_store: &mut DataStore<'a>, call: Span) -> Result<Value<'a>, MyLangError> {
+        self.signature().check(self.name, vars.len()).map_err(|e| e.at(call))?;
+        self.enter(data_store, call, |data_store| {
+            for &var in captured {
+                let val = data_store.get(var)?;
+                data_store.push(val);
+            }
+            let (named, rest) = vars.split_at(self.args.len());
+            for val in named {
+                let val = val.evaluate(data_store)?;
+                data_store.push(val);
+            }
+            if self.rest.is_some() {
+                let rest = rest.iter()
+                    .map(|val| val.evaluate(data_store))
+                    .collect::<Result<_, MyLangError>>()?;
+                data_store.push(Value::list(rest));
+            }
+            Ok(())
+        })
+    }
+
+    // run the function as a value, with the copies of the variables it captured when the value was made and
+    // arguments that have already been worked out
+    pub fn call(&self, captured: &[Value<'a>], mut args: Vec<Value<'a>>, data_store: &mut DataStore<'a>, call: Span) -> Result<Value<'a>, MyLangError> {
+        self.signature().check(self.name, args.len()).map_err(|e| e.at(call))?;
+        self.enter(data_store, call, |data_store| {
+            let rest = args.split_off(self.args.len());
+            for val in captured.iter().cloned().chain(args) {
+                data_store.push(val);
+            }
+            if self.rest.is_some() {
+                data_store.push(Value::list(rest));
+            }
+            Ok(())
+        })
+    }
+
+    // the function's frame starts with `res`, then the captured variables, the arguments and then the rest argument,
+    // see `Scope::function`. `push_args` puts them there while the caller's frame is still the current one
+    fn enter(&self, data_store: &mut DataStore<'a>, call: Span, push_args: impl FnOnce(&mut DataStore<'a>) -> Result<(), MyLangError>) -> Result<Value<'a>, MyLangError> {
+        if data_store.depth() >= data_store.call_depth() {
+            return Err(ErrorKind::RecursionLimit(data_store.call_depth()).at(call));
         }
-        let mut function_data_store = DataStore::new();
-        function_data_store.expand();
-        function_data_store.put("res", 0);
-        // get arg values from outer program and load into user function with arg names
-        self.args.iter().zip(vars)
-            .for_each(|(k, val)| {
-                let val = val.evaluate(data_store).unwrap();
-                function_data_store.put(k, val);
-            });
-        self.code.run_with(&mut function_data_store);
-        function_data_store.get("res")
-    }
-
-    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>) -> UserFunction<'a> {
-        UserFunction{
-            code: self.code.optimise(user_fns),
-            args: self.args.clone()
+        data_store.check_stack().map_err(|e| e.at(call))?;
+        let base = data_store.top();
+        data_store.push(Value::Int(0));
+        let args = push_args(data_store);
+        let caller = data_store.enter(base);
+        let result = args.and_then(|_| self.run(data_store).map_err(|e| e.called_from(call)));
+        data_store.leave(caller);
+        result
+    }
+
+    fn run(&self, data_store: &mut DataStore<'a>) -> Result<Value<'a>, MyLangError> {
+        let code = self.optimised.get().unwrap_or(&self.code);
+        if let Flow::Return(Some(val)) = code.run_with(data_store)? {
+            return Ok(val);
         }
+        data_store.get(Variable { name: "res", slot: Some(Slot::Local(0)) })
     }
-}
\ No newline at end of file
+
+    // optimise a function declared outside of any other, which can read the main program's `globals`. optimising
+    // a function a second time does nothing
+    pub fn optimise(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, globals: &Rc<[&'a str]>) -> Result<(), MyLangError> {
+        if self.optimised.get().is_none() {
+            let mut scope = Scope::function(Rc::clone(globals), &[], &self.args, self.rest);
+            let code = self.code.optimise(user_fns, &mut scope)?;
+            let _ = self.optimised.set(code);
+        }
+        Ok(())
+    }
+
+    // optimise a function defined inside another one, at the point in it where it's defined
+    pub fn optimise_nested(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &mut Scope<'a>) -> Result<(), MyLangError> {
+        let code = self.code.optimise(user_fns, &mut scope.nested(self))?;
+        let _ = self.optimised.set(code);
+        Ok(())
+    }
+
+    // optimise a function without a name, giving back the variables of `scope` it captures
+    pub fn optimise_lambda(&'a self, user_fns: &'a HashMap<&'a str, UserFunction<'a>>, scope: &Scope<'a>) -> Result<Vec<Variable<'a>>, MyLangError> {
+        let (mut inner, captured) = scope.lambda(self);
+        let code = self.code.optimise(user_fns, &mut inner)?;
+        let _ = self.optimised.set(code);
+        Ok(captured)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the values of the main program's top level variables once it has run
+    fn run(text: &str) -> Vec<String> {
+        run_on(text, crate::MAX_CALL_DEPTH, crate::STACK_SIZE).unwrap()
+    }
+
+    // run with at most `call_depth` calls in progress, and `stack_size` of stack to make them with
+    fn run_on(text: &str, call_depth: usize, stack_size: usize) -> Result<Vec<String>, ErrorKind> {
+        let mut user_fns = HashMap::new();
+        let program = Program::parse(text, 0, &mut user_fns).unwrap();
+        let globals = program.globals();
+        user_fns.values().try_for_each(|func| func.optimise(&user_fns, &globals)).unwrap();
+        let program = program.optimise(&user_fns, &mut Scope::new(Rc::clone(&globals))).unwrap();
+        let mut data_store = DataStore::new().with_call_depth(call_depth).with_stack_size(stack_size);
+        program.run_with(&mut data_store).map_err(|e| e.kind().clone())?;
+        Ok((0..globals.len())
+            .map(|slot| data_store.get(Variable { name: globals[slot], slot: Some(Slot::Global(slot)) }).unwrap().to_string())
+            .collect())
+    }
+
+    #[test]
+    fn captured_variables_are_copied() {
+        let globals = run("
+            func make_counter {
+                count: 0
+                res: func {
+                    count: + count 1
+                    res: count
+                }
+            }
+            counter: make_counter
+            a: call counter
+            b: call counter
+        ");
+        assert_eq!(globals[1..], ["1", "1"]);
+    }
+
+    #[test]
+    fn captured_lists_are_shared() {
+        let globals = run("
+            func make_counter {
+                count: [0]
+                res: func {
+                    count[0]: + count[0] 1
+                    res: count[0]
+                }
+            }
+            counter: make_counter
+            a: call counter
+            b: call counter
+        ");
+        assert_eq!(globals[1..], ["1", "2"]);
+    }
+
+    #[test]
+    fn recursion_deeper_than_the_call_depth_is_an_error() {
+        let text = "func f n {\n    res: ? (== n 0) 0 (f (- n 1))\n}\nx: f 10";
+        assert_eq!(run_on(text, 5, crate::STACK_SIZE), Err(ErrorKind::RecursionLimit(5)));
+        assert_eq!(run_on(text, 11, crate::STACK_SIZE), Ok(vec![String::from("0")]));
+    }
+
+    #[test]
+    fn running_out_of_stack_is_an_error_before_the_call_depth() {
+        let text = "func f n {\n    res: + 0 (+ 0 (+ 0 (? (== n 0) 0 (f (- n 1)))))\n}\nx: f 100000";
+        assert_eq!(run_on(text, usize::MAX, 256 * 1024), Err(ErrorKind::StackOverflow));
+    }
+
+    // tests run on threads with rust's default 2MB of stack
+    #[test]
+    fn the_default_limits_fit_in_a_default_thread() {
+        let text = "func f n {\n    res: + 0 (+ 0 (+ 0 (? (== n 0) 0 (f (- n 1)))))\n}\nx: f 100000";
+        let result = run_on(text, crate::MAX_CALL_DEPTH, crate::STACK_SIZE);
+        assert!(matches!(result, Err(ErrorKind::RecursionLimit(_) | ErrorKind::StackOverflow)), "{:?}", result);
+    }
+}
@@ -0,0 +1,554 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::{BigInt, ErrorKind, MyLangError};
+use crate::user_function::Closure;
+
+// everything an expression can evaluate to and a variable can hold. it's kept to two words, a whole word of
+// tag and a word of payload, as values are copied in and out of the data store and results all the time. a
+// byte tag measured slower on the benchmark, though just why wasn't pinned down
+#[derive(Debug, Clone)]
+#[repr(u64)]
+pub enum Value<'a> {
+    Int(i64),
+    // an int too big or too small to fit in an `Int`. ints that fit are always held as an `Int`, see `Value::big`
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    Bool(bool),
+    // an `Rc<String>` rather than an `Rc<str>` keeps every value to two words, and so a result no bigger than the value in it
+    Str(Rc<String>),
+    // lists and maps are shared rather than copied, so changes made through one variable are seen by all of them
+    List(Rc<RefCell<Vec<Value<'a>>>>),
+    Map(Rc<RefCell<HashMap<Key, Value<'a>>>>),
+    Function(Rc<Closure<'a>>),
+    // structs are shared like lists and maps
+    Struct(Rc<Record<'a>>),
+}
+
+// a type declared with `struct NAME FIELDS`, or one of the variants of an enum
+#[derive(Debug)]
+pub struct StructType<'a> {
+    pub name: &'a str,
+    pub fields: Vec<&'a str>,
+    // the enum a variant belongs to, which is the type its values have
+    pub enum_name: Option<&'a str>,
+}
+
+// a value of a struct type, with a value for each of its fields in the order they were declared
+#[derive(Debug)]
+pub struct Record<'a> {
+    pub kind: Rc<StructType<'a>>,
+    pub fields: RefCell<Vec<Value<'a>>>,
+}
+
+// the values that can be used as map keys. ints sort before strings
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Int(i64),
+    Str(Rc<String>),
+}
+
+impl<'a> Value<'a> {
+    // how the type is referred to in error messages. a struct is referred to by the name of its type
+    pub fn type_name(&self) -> &'a str {
+        match self {
+            Value::Int(_) => "int",
+            Value::BigInt(_) => "big int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "str",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Function(_) => "func",
+            Value::Struct(record) => record.kind.enum_name.unwrap_or(record.kind.name),
+        }
+    }
+
+    pub fn list(items: Vec<Value<'a>>) -> Value<'a> {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+
+    // an int of any size, held as an `Int` if it fits in one
+    pub fn big(n: BigInt) -> Value<'a> {
+        match n.to_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::BigInt(Rc::new(n)),
+        }
+    }
+
+    pub fn map(entries: HashMap<Key, Value<'a>>) -> Value<'a> {
+        Value::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    // whether a condition holds. ints are true unless they are 0, anything other than a bool or an
+    // int can't be used as a condition. `name` is what the condition belongs to
+    pub fn truthy(&self, name: &str) -> Result<bool, MyLangError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            Value::Int(i) => Ok(*i != 0),
+            Value::BigInt(_) => Ok(true),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    // an int that's too big for an `Int` can't be used where only an `Int` will do, like as an index
+    pub fn as_int(&self, name: &str) -> Result<i64, MyLangError> {
+        match self {
+            Value::Int(i) => Ok(*i),
+            Value::BigInt(_) => Err(ErrorKind::BigIntUnsupported(String::from(name)).into()),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    // an int of any size, or `None` if this isn't an int
+    pub fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(i) => Some(BigInt::from(*i)),
+            Value::BigInt(n) => Some(BigInt::clone(n)),
+            _ => None,
+        }
+    }
+
+    // -1, 0 or 1 for an int of any size
+    pub fn int_signum(&self, name: &str) -> Result<i64, MyLangError> {
+        match self {
+            Value::Int(i) => Ok(i.signum()),
+            Value::BigInt(n) => Ok(n.signum()),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    // ints can be used anywhere a float can
+    pub fn as_float(&self, name: &str) -> Result<f64, MyLangError> {
+        match self {
+            Value::Int(i) => Ok(*i as f64),
+            Value::BigInt(n) => Ok(n.to_f64()),
+            Value::Float(x) => Ok(*x),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    pub fn as_list(&self, name: &str) -> Result<&Rc<RefCell<Vec<Value<'a>>>>, MyLangError> {
+        match self {
+            Value::List(list) => Ok(list),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    pub fn as_map(&self, name: &str) -> Result<&Rc<RefCell<HashMap<Key, Value<'a>>>>, MyLangError> {
+        match self {
+            Value::Map(map) => Ok(map),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    // the items of a list, or the keys of a map in order
+    pub fn items(&self, name: &str) -> Result<Vec<Value<'a>>, MyLangError> {
+        match self {
+            Value::List(list) => Ok(list.borrow().clone()),
+            Value::Map(map) => Ok(sorted_keys(map)),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    pub fn as_function(&self, name: &str) -> Result<&Rc<Closure<'a>>, MyLangError> {
+        match self {
+            Value::Function(func) => Ok(func),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    pub fn as_key(&self, name: &str) -> Result<Key, MyLangError> {
+        match self {
+            Value::Int(i) => Ok(Key::Int(*i)),
+            Value::Str(s) => Ok(Key::Str(Rc::clone(s))),
+            Value::BigInt(_) => Err(ErrorKind::BigIntUnsupported(String::from(name)).into()),
+            _ => Err(type_error(name, &[self])),
+        }
+    }
+
+    // the item at `index` of a list, or the value for the key `index` of a map
+    pub fn index(&self, index: &Value<'a>) -> Result<Value<'a>, MyLangError> {
+        match self {
+            Value::List(list) => {
+                let list = list.borrow();
+                let i = list_index(index, list.len())?;
+                Ok(list[i].clone())
+            }
+            Value::Map(map) => {
+                let key = index.as_key("index")?;
+                map.borrow().get(&key)
+                    .cloned()
+                    .ok_or_else(|| ErrorKind::MissingKey(key.to_string()).into())
+            }
+            _ => Err(type_error("index", &[self, index])),
+        }
+    }
+
+    // replace the item at `index` of a list, or set the value for the key `index` of a map
+    pub fn set_index(&self, index: &Value<'a>, val: Value<'a>) -> Result<(), MyLangError> {
+        match self {
+            Value::List(list) => {
+                let mut list = list.borrow_mut();
+                let i = list_index(index, list.len())?;
+                list[i] = val;
+            }
+            Value::Map(map) => {
+                let key = index.as_key("index")?;
+                map.borrow_mut().insert(key, val);
+            }
+            _ => return Err(type_error("index", &[self, index])),
+        }
+        Ok(())
+    }
+
+    // the value of one of a struct's fields
+    pub fn field(&self, field: &str) -> Result<Value<'a>, MyLangError> {
+        let record = self.as_struct(field)?;
+        let i = record.field_index(field)?;
+        Ok(record.fields.borrow()[i].clone())
+    }
+
+    pub fn set_field(&self, field: &str, val: Value<'a>) -> Result<(), MyLangError> {
+        let record = self.as_struct(field)?;
+        let i = record.field_index(field)?;
+        record.fields.borrow_mut()[i] = val;
+        Ok(())
+    }
+
+    fn as_struct(&self, field: &str) -> Result<&Rc<Record<'a>>, MyLangError> {
+        match self {
+            Value::Struct(record) => Ok(record),
+            _ => Err(type_error(&format!(".{}", field), &[self])),
+        }
+    }
+
+    // ints and floats can be compared with each other, other values only with their own type. lists are
+    // compared item by item. maps can only be checked for equality, as can functions, which are equal if they're
+    // the same function with the same captured variables, and structs, which are equal if they're the same type
+    // with the same fields
+    pub fn compare(&self, other: &Value<'a>) -> Option<Ordering> {
+        self.compare_within(other, &mut Vec::new())
+    }
+
+    // `within` holds the pairs of lists, maps, functions and structs being compared further out. a list can
+    // hold itself, and comparing the same pair again inside itself would never end, so a pair that's already
+    // being compared counts as equal there, leaving it to the rest of their items to tell them apart
+    fn compare_within(&self, other: &Value<'a>, within: &mut Vec<(usize, usize)>) -> Option<Ordering> {
+        let pair = match (self, other) {
+            (Value::List(i), Value::List(j)) => Some((Rc::as_ptr(i) as usize, Rc::as_ptr(j) as usize)),
+            (Value::Map(i), Value::Map(j)) => Some((Rc::as_ptr(i) as usize, Rc::as_ptr(j) as usize)),
+            (Value::Function(i), Value::Function(j)) => Some((Rc::as_ptr(i) as usize, Rc::as_ptr(j) as usize)),
+            (Value::Struct(i), Value::Struct(j)) => Some((Rc::as_ptr(i) as usize, Rc::as_ptr(j) as usize)),
+            _ => None,
+        };
+        if let Some(pair) = pair {
+            if within.contains(&pair) {
+                return Some(Ordering::Equal);
+            }
+            within.push(pair);
+        }
+        let ordering = match (self, other) {
+            (Value::Int(i), Value::Int(j)) => Some(i.cmp(j)),
+            (Value::Int(i), Value::Float(j)) => (*i as f64).partial_cmp(j),
+            (Value::Float(i), Value::Int(j)) => i.partial_cmp(&(*j as f64)),
+            (Value::Float(i), Value::Float(j)) => i.partial_cmp(j),
+            (Value::BigInt(i), Value::BigInt(j)) => Some(i.cmp(j)),
+            (Value::BigInt(i), Value::Int(j)) => Some(i.as_ref().cmp(&BigInt::from(*j))),
+            (Value::Int(i), Value::BigInt(j)) => Some(BigInt::from(*i).cmp(j)),
+            (Value::BigInt(i), Value::Float(j)) => i.to_f64().partial_cmp(j),
+            (Value::Float(i), Value::BigInt(j)) => i.partial_cmp(&j.to_f64()),
+            (Value::Bool(i), Value::Bool(j)) => Some(i.cmp(j)),
+            (Value::Str(i), Value::Str(j)) => Some(i.cmp(j)),
+            (Value::List(i), Value::List(j)) => compare_items(&i.borrow(), &j.borrow(), within),
+            (Value::Map(i), Value::Map(j)) => {
+                let (i, j) = (i.borrow(), j.borrow());
+                let equal = i.len() == j.len()
+                    && i.iter().all(|(k, v)| j.get(k).is_some_and(|w| v.compare_within(w, within) == Some(Ordering::Equal)));
+                if equal { Some(Ordering::Equal) } else { None }
+            }
+            (Value::Function(i), Value::Function(j)) => {
+                let equal = std::ptr::eq(i.func, j.func) && compare_items(&i.captured, &j.captured, within) == Some(Ordering::Equal);
+                if equal { Some(Ordering::Equal) } else { None }
+            }
+            (Value::Struct(i), Value::Struct(j)) => {
+                let equal = Rc::ptr_eq(&i.kind, &j.kind)
+                    && compare_items(&i.fields.borrow(), &j.fields.borrow(), within) == Some(Ordering::Equal);
+                if equal { Some(Ordering::Equal) } else { None }
+            }
+            _ => None,
+        };
+        if pair.is_some() {
+            within.pop();
+        }
+        ordering
+    }
+}
+
+// two lists of values compared item by item, and then by length if one starts with the other
+fn compare_items<'a>(i: &[Value<'a>], j: &[Value<'a>], within: &mut Vec<(usize, usize)>) -> Option<Ordering> {
+    for (a, b) in i.iter().zip(j.iter()) {
+        match a.compare_within(b, within)? {
+            Ordering::Equal => (),
+            ordering => return Some(ordering),
+        }
+    }
+    Some(i.len().cmp(&j.len()))
+}
+
+// values of different types are never equal, other than ints and floats holding the same number
+impl<'a> PartialEq for Value<'a> {
+    fn eq(&self, other: &Value<'a>) -> bool {
+        if let (Value::Int(a), Value::Int(b)) = (self, other) {
+            return a == b;
+        }
+        self.compare(other) == Some(Ordering::Equal)
+    }
+}
+
+impl From<Key> for Value<'_> {
+    fn from(key: Key) -> Self {
+        match key {
+            Key::Int(i) => Value::Int(i),
+            Key::Str(s) => Value::Str(s),
+        }
+    }
+}
+
+// floats always show a decimal point so they can be told apart from ints
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_value(f, self, &mut Vec::new())
+    }
+}
+
+// `within` holds the lists, maps and structs being shown further out. one that holds itself is shown as `[...]`,
+// or `NAME(...)` for a struct, where it appears inside itself
+fn write_value(f: &mut fmt::Formatter<'_>, val: &Value, within: &mut Vec<usize>) -> fmt::Result {
+    let container = match val {
+        Value::List(list) => Rc::as_ptr(list) as usize,
+        Value::Map(map) => Rc::as_ptr(map) as usize,
+        Value::Struct(record) => Rc::as_ptr(record) as usize,
+        _ => return write_contents(f, val, within),
+    };
+    if within.contains(&container) {
+        return match val {
+            Value::Struct(record) => write!(f, "{}(...)", record.kind.name),
+            _ => write!(f, "[...]"),
+        };
+    }
+    within.push(container);
+    let result = write_contents(f, val, within);
+    within.pop();
+    result
+}
+
+fn write_contents(f: &mut fmt::Formatter<'_>, val: &Value, within: &mut Vec<usize>) -> fmt::Result {
+    match val {
+        Value::Int(i) => write!(f, "{}", i),
+        Value::BigInt(n) => write!(f, "{}", n),
+        Value::Float(x) if x.is_finite() && x.fract() == 0.0 => write!(f, "{:.1}", x),
+        Value::Float(x) => write!(f, "{}", x),
+        Value::Bool(b) => write!(f, "{}", b),
+        Value::Str(s) => write!(f, "{}", s),
+        Value::List(list) => {
+            write!(f, "[")?;
+            for (i, item) in list.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write_item(f, item, within)?;
+            }
+            write!(f, "]")
+        }
+        // entries are shown in key order, so the same map always prints the same way
+        Value::Map(map) => {
+            let map = map.borrow();
+            if map.is_empty() {
+                return write!(f, "[:]");
+            }
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            write!(f, "[")?;
+            for (i, (key, val)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}: ", key)?;
+                write_item(f, val, within)?;
+            }
+            write!(f, "]")
+        }
+        Value::Function(func) => write!(f, "<func {}>", func.func.name),
+        // a variant without any fields is just its name
+        Value::Struct(record) if record.kind.enum_name.is_some() && record.kind.fields.is_empty() => {
+            write!(f, "{}", record.kind.name)
+        }
+        Value::Struct(record) => {
+            write!(f, "{}(", record.kind.name)?;
+            for (i, (field, val)) in record.kind.fields.iter().zip(record.fields.borrow().iter()).enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}: ", field)?;
+                write_item(f, val, within)?;
+            }
+            write!(f, ")")
+        }
+    }
+}
+
+impl<'a> Record<'a> {
+    fn field_index(&self, field: &str) -> Result<usize, MyLangError> {
+        self.kind.fields.iter()
+            .position(|&f| f == field)
+            .ok_or_else(|| ErrorKind::MissingField {
+                name: String::from(self.kind.name),
+                field: String::from(field),
+            }.into())
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Int(i) => write!(f, "{}", i),
+            Key::Str(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+// strings inside a list or map keep their quotes so `["a b"]` and `["a" "b"]` can be told apart
+fn write_item(f: &mut fmt::Formatter<'_>, item: &Value, within: &mut Vec<usize>) -> fmt::Result {
+    match item {
+        Value::Str(s) => write!(f, "{:?}", s),
+        item => write_value(f, item, within),
+    }
+}
+
+// the keys of a map as values, in order
+pub fn sorted_keys<'a>(map: &RefCell<HashMap<Key, Value>>) -> Vec<Value<'a>> {
+    let mut keys = map.borrow().keys().cloned().collect::<Vec<_>>();
+    keys.sort();
+    keys.into_iter().map(Value::from).collect()
+}
+
+// check an index is an int that is within a list of length `len`
+pub fn list_index(index: &Value, len: usize) -> Result<usize, MyLangError> {
+    let i = index.as_int("index")?;
+    usize::try_from(i).ok()
+        .filter(|&i| i < len)
+        .ok_or_else(|| ErrorKind::IndexOutOfRange { index: i, len }.into())
+}
+
+// `name` was given values of types it can't work with
+pub fn type_error(name: &str, values: &[&Value]) -> MyLangError {
+    let found = values.iter()
+        .map(|v| v.type_name())
+        .collect::<Vec<_>>()
+        .join(" and ");
+    ErrorKind::Type {
+        name: String::from(name),
+        found,
+    }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::tests::run;
+    use crate::Span;
+
+    // `[1 2]` with itself pushed onto the end
+    fn holding_itself<'a>() -> Value<'a> {
+        let list = Value::list(vec![Value::Int(1), Value::Int(2)]);
+        list.as_list("test").unwrap().borrow_mut().push(list.clone());
+        list
+    }
+
+    #[test]
+    fn a_list_holding_itself_is_shown_with_dots_inside() {
+        assert_eq!(holding_itself().to_string(), "[1 2 [...]]");
+        let list = Value::list(vec![Value::Int(1)]);
+        assert_eq!(Value::list(vec![list.clone(), list]).to_string(), "[[1] [1]]");
+    }
+
+    #[test]
+    fn a_map_or_struct_holding_itself_is_shown_with_dots_inside() {
+        let map = Value::map(HashMap::new());
+        map.set_index(&Value::Int(1), map.clone()).unwrap();
+        assert_eq!(map.to_string(), "[1: [...]]");
+
+        let kind = Rc::new(StructType { name: "p", fields: vec!["a"], enum_name: None });
+        let record = Value::Struct(Rc::new(Record { kind, fields: RefCell::new(vec![Value::Int(0)]) }));
+        record.set_field("a", record.clone()).unwrap();
+        assert_eq!(record.to_string(), "p(a: p(...))");
+    }
+
+    #[test]
+    fn struct_fields_are_read_and_assigned_by_name() {
+        let globals = run("
+            struct point x y
+            p: point 1 2
+            q: p
+            q.x: 5
+            p.y: + p.y 1
+            r: [(point 3 4)]
+            r[0].x: p.x
+            same: == (point 1 2) (point 1 2)
+        ").unwrap();
+        assert_eq!(globals, ["point(x: 5 y: 3)", "point(x: 5 y: 3)", "[point(x: 5 y: 4)]", "true"]);
+    }
+
+    #[test]
+    fn a_struct_only_has_the_fields_it_was_declared_with() {
+        let error = run("struct point x y\np: point 1 2\nx: p.z").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::MissingField { name: String::from("point"), field: String::from("z") });
+        assert_eq!(error.span(), Some(Span { file: 0, line: 3, column: 3, len: 3 }));
+        let error = run("struct point x y\np: point 1 2\np.z: 1").unwrap_err();
+        assert_eq!(error.span(), Some(Span { file: 0, line: 3, column: 0, len: 6 }));
+        let error = run("x: 5\ny: x.a").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::Type { name: String::from(".a"), found: String::from("int") });
+    }
+
+    #[test]
+    fn a_struct_is_made_with_a_value_for_each_field() {
+        let error = run("struct point x y\np: point 1").unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::Arity { name: String::from("point"), expected: 2, found: 1 });
+        assert_eq!(error.span(), Some(Span { file: 0, line: 2, column: 3, len: 7 }));
+        let error = run("struct point x x").unwrap_err();
+        assert_eq!(error.span(), Some(Span { file: 0, line: 1, column: 15, len: 1 }));
+    }
+
+    #[test]
+    fn a_value_and_a_result_holding_one_are_two_words() {
+        assert_eq!(std::mem::size_of::<Value>(), 16);
+        assert_eq!(std::mem::size_of::<Option<Value>>(), 16);
+        assert_eq!(std::mem::size_of::<Result<Value, MyLangError>>(), 16);
+        assert_eq!(std::mem::size_of::<Result<Option<Value>, MyLangError>>(), 16);
+    }
+
+    #[test]
+    fn ints_are_equal_to_the_same_number_whatever_its_type() {
+        assert!(Value::Int(3) == Value::Int(3));
+        assert!(Value::Int(3) != Value::Int(4));
+        assert!(Value::Int(3) == Value::Float(3.0));
+        assert!(Value::Float(3.0) == Value::Int(3));
+        assert!(Value::Int(3) != Value::Str(Rc::new(String::from("3"))));
+        assert!(Value::big(BigInt::from(i128::from(i64::MAX) + 1)) != Value::Int(i64::MAX));
+    }
+
+    #[test]
+    fn lists_holding_themselves_can_be_compared() {
+        let (a, b) = (holding_itself(), holding_itself());
+        assert!(a == a);
+        assert!(a == b);
+        b.as_list("test").unwrap().borrow_mut()[0] = Value::Int(0);
+        assert_eq!(a.compare(&b), Some(Ordering::Greater));
+        assert_eq!(b.compare(&a), Some(Ordering::Less));
+    }
+}
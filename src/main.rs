@@ -1,21 +1,261 @@
+use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
 
-use crate::lib::Program;
-use std::collections::HashMap;
+use my_lang::{load_imports, DataStore, MyLangError, Overflow, Program, Scope, Sources};
+
+// exit codes follow the BSD sysexits convention
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_USAGE: i32 = 64;
+const EXIT_PARSE_FAILURE: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_RUNTIME_FAILURE: i32 = 70;
+
+// how deeply recursive programs run from the command line can go. a debug build uses many times more stack for
+// each call, so it allows fewer
+const CALL_DEPTH: usize = if cfg!(debug_assertions) { 1_000 } else { 10_000 };
+
+// the program runs on its own thread with this much stack. running out of it before reaching `CALL_DEPTH` is an
+// error like reaching it is
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+// how much of the thread's stack is kept back for the built in that runs out of it to finish up
+const STACK_SPARE: usize = 1024 * 1024;
+
+const USAGE: &str = "\
+usage: my_lang [-h] [-I <dir>...] [--overflow <policy>] <script> [args...]
+       my_lang [-h] [-I <dir>...] [--overflow <policy>] - [args...]
+       my_lang [-h] [-I <dir>...] [--overflow <policy>] -e <source> [args...]
+
+  <script>     run the program in the given file
+  -            read the program from stdin
+  -e <source>  run the given program text
+  -I <dir>     look for imported files in the given directory too
+  --overflow <policy>
+               what int arithmetic does when its result is too big for a 64 bit int, one of
+               promote to a big int (the default), error, wrap or saturate
+  -h, --help   show this message
+
+any following args are passed to the program, which can read them with `argc` and `arg N`.
+imported files are looked for relative to the file importing them, then in each -I directory,
+then in each directory listed in the MY_LANG_PATH environment variable";
+
+enum Source {
+    File(String),
+    Stdin,
+    Inline(String),
+}
 
-mod lib;
+struct Options {
+    source: Source,
+    search_path: Vec<PathBuf>,
+    overflow: Overflow,
+    args: Vec<String>,
+}
 
 fn main() {
-    let program_text = fs::read_to_string("programs/test.jcw").unwrap();
-    let program_lines = program_text.lines();
-    let program_lines: Vec<&str> = program_lines
-        .map(str::trim)
-        .collect();
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            process::exit(EXIT_SUCCESS);
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    let program_text = match read_source(&options.source) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("error: could not read program: {}", err);
+            process::exit(EXIT_NO_INPUT);
+        }
+    };
+
+    let mut search_path = options.search_path;
+    if let Some(dirs) = env::var_os("MY_LANG_PATH") {
+        search_path.extend(env::split_paths(&dirs));
+    }
+    let mut sources = Sources::new();
+    let file = sources.add(source_name(&options.source), program_text);
+    let path = match &options.source {
+        Source::File(path) => Some(Path::new(path)),
+        _ => None,
+    };
+    let files = match load_imports(&mut sources, file, path, search_path) {
+        Ok(files) => files,
+        Err(err) => process::exit(report(&sources, err, EXIT_PARSE_FAILURE)),
+    };
+
+    let (args, overflow) = (options.args, options.overflow);
+    let interpreter = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(&sources, &files, args, overflow));
+    match interpreter {
+        Ok(interpreter) => process::exit(interpreter.join().unwrap_or(EXIT_RUNTIME_FAILURE)),
+        Err(err) => {
+            eprintln!("error: could not start the interpreter: {}", err);
+            process::exit(EXIT_RUNTIME_FAILURE);
+        }
+    }
+}
+
+// the first non-option argument is the script, everything after it belongs to the script
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut search_path = Vec::new();
+    let mut overflow = Overflow::default();
+    let source = loop {
+        match args.next().as_deref() {
+            Some("-h") | Some("--help") => return Ok(None),
+            Some("-I") => match args.next() {
+                Some(dir) => search_path.push(PathBuf::from(dir)),
+                None => return Err(String::from("-I needs a directory")),
+            },
+            Some("--overflow") => match args.next() {
+                Some(policy) => overflow = policy.parse()?,
+                None => return Err(String::from("--overflow needs a policy")),
+            },
+            Some("-e") => match args.next() {
+                Some(text) => break Source::Inline(text),
+                None => return Err(String::from("-e needs some program text")),
+            },
+            Some("-") => break Source::Stdin,
+            Some(flag) if flag.starts_with('-') => return Err(format!("unknown option \"{}\"", flag)),
+            Some(path) => break Source::File(String::from(path)),
+            None => return Err(String::from("no program given")),
+        }
+    };
+
+    Ok(Some(Options {
+        source,
+        search_path,
+        overflow,
+        args: args.collect(),
+    }))
+}
+
+// what the program is called in error messages
+fn source_name(source: &Source) -> String {
+    match source {
+        Source::File(path) => path.clone(),
+        Source::Stdin => String::from("<stdin>"),
+        Source::Inline(_) => String::from("<command line>"),
+    }
+}
+
+fn read_source(source: &Source) -> io::Result<String> {
+    match source {
+        Source::File(path) => fs::read_to_string(path),
+        Source::Stdin => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            Ok(text)
+        }
+        Source::Inline(text) => Ok(text.clone()),
+    }
+}
+
+// `files` is the program along with the files it imports, see `load_imports`
+fn run(sources: &Sources, files: &[u32], args: Vec<String>, overflow: Overflow) -> i32 {
     let mut user_fns = HashMap::new();
-    let program = Program::from_lines(&mut program_lines.iter(), &mut user_fns);
-    let optimised_user_funcs = user_fns.iter()
-        .map(|(&k, v)| (k, v.optimise(&user_fns)))
-        .collect();
-    let program = program.optimise(&optimised_user_funcs);
-    program.start();
+    let program = match Program::parse_files(sources, files, &mut user_fns) {
+        Ok(program) => program,
+        Err(err) => return report(sources, err, EXIT_PARSE_FAILURE),
+    };
+
+    let globals = program.globals();
+    if let Err(err) = user_fns.values().try_for_each(|func| func.optimise(&user_fns, &globals)) {
+        return report(sources, err, EXIT_PARSE_FAILURE);
+    }
+    let program = match program.optimise(&user_fns, &mut Scope::new(globals)) {
+        Ok(program) => program,
+        Err(err) => return report(sources, err, EXIT_PARSE_FAILURE),
+    };
+
+    let data_store = DataStore::with_args(args).with_overflow(overflow).with_call_depth(CALL_DEPTH).with_stack_size(STACK_SIZE - STACK_SPARE);
+    match program.start_with(data_store) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(err) => report(sources, err, EXIT_RUNTIME_FAILURE),
+    }
+}
+
+fn report(sources: &Sources, err: MyLangError, exit_code: i32) -> i32 {
+    eprint!("{}", err.render(sources));
+    exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_options(args.iter().map(|arg| String::from(*arg)))
+    }
+
+    #[test]
+    fn the_first_argument_that_isnt_an_option_is_the_script() {
+        let options = options(&["-I", "lib", "--overflow", "wrap", "a.jcw", "-e", "x"]).unwrap().unwrap();
+        assert!(matches!(&options.source, Source::File(path) if path == "a.jcw"));
+        assert_eq!(options.search_path, [PathBuf::from("lib")]);
+        assert_eq!(options.overflow, Overflow::Wrap);
+        assert_eq!(options.args, ["-e", "x"]);
+    }
+
+    #[test]
+    fn a_program_can_come_from_stdin_or_the_command_line() {
+        let stdin = options(&["-", "1", "2"]).unwrap().unwrap();
+        assert!(matches!(stdin.source, Source::Stdin));
+        assert_eq!(stdin.args, ["1", "2"]);
+        assert_eq!(source_name(&stdin.source), "<stdin>");
+
+        let inline = options(&["-e", "print 1"]).unwrap().unwrap();
+        assert!(matches!(&inline.source, Source::Inline(text) if text == "print 1"));
+        assert_eq!(inline.overflow, Overflow::Promote);
+        assert_eq!(read_source(&inline.source).unwrap(), "print 1");
+        assert_eq!(source_name(&inline.source), "<command line>");
+    }
+
+    #[test]
+    fn help_stops_before_anything_else_is_read() {
+        assert!(options(&["-h"]).unwrap().is_none());
+        assert!(options(&["-I", "lib", "--help", "--bad"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_usage_is_an_error() {
+        assert_eq!(options(&[]).err().unwrap(), "no program given");
+        assert_eq!(options(&["-x", "a.jcw"]).err().unwrap(), "unknown option \"-x\"");
+        assert_eq!(options(&["-e"]).err().unwrap(), "-e needs some program text");
+        assert_eq!(options(&["a.jcw", "-I"]).unwrap().unwrap().args, ["-I"]);
+        assert_eq!(options(&["-I"]).err().unwrap(), "-I needs a directory");
+        assert_eq!(options(&["--overflow"]).err().unwrap(), "--overflow needs a policy");
+        assert!(options(&["--overflow", "clamp", "a.jcw"]).err().unwrap().starts_with("unknown overflow policy \"clamp\""));
+    }
+
+    #[test]
+    fn a_missing_script_cant_be_read() {
+        assert!(read_source(&Source::File(String::from("no/such/file.jcw"))).is_err());
+    }
+
+    // the exit code of running `text` as the whole program
+    fn exit_code(text: &str) -> i32 {
+        let mut sources = Sources::new();
+        let file = sources.add(String::from("test.jcw"), String::from(text));
+        run(&sources, &[file], Vec::new(), Overflow::Error)
+    }
+
+    #[test]
+    fn the_exit_code_says_whether_the_program_parsed_and_ran() {
+        assert_eq!(exit_code("x: + 1 2"), EXIT_SUCCESS);
+        assert_eq!(exit_code("x: + 1"), EXIT_PARSE_FAILURE);
+        assert_eq!(exit_code("x: y"), EXIT_PARSE_FAILURE);
+        assert_eq!(exit_code("x: / 1 0"), EXIT_RUNTIME_FAILURE);
+        assert_eq!(exit_code("x: + 9223372036854775807 1"), EXIT_RUNTIME_FAILURE);
+    }
 }